@@ -0,0 +1,178 @@
+//! Times sorting a generated image with a few pipelines, at 8 and 16 bits, on a single thread.
+//! The rows and columns are also sorted the way they were before the keys were cached, as a
+//! baseline.
+
+use std::time::{Duration, Instant};
+
+use pixelsort_core::{
+    sort_image, stage_intervals, Channel, IntervalPixel, Pipeline, SortDirection, Stage,
+};
+
+const WIDTH: usize = 2048;
+const HEIGHT: usize = 1024;
+const RUNS: usize = 5;
+
+// A gradient with noise on top, so the sorted intervals have all kinds of lengths.
+fn image() -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut noise = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % 64) as usize
+    };
+    (0..WIDTH * HEIGHT)
+        .flat_map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            [
+                (x * 192 / WIDTH + noise()) as u8,
+                (y * 192 / HEIGHT + noise()) as u8,
+                ((x + y) * 96 / (WIDTH + HEIGHT) + noise() * 2) as u8,
+                255,
+            ]
+        })
+        .collect()
+}
+
+// Sort like sort_image did with a comparison sort, which computes the keys of both pixels on
+// every comparison, and a new buffer for every row and interval. Only rows and columns are
+// sorted, the paths of other directions aren't public.
+fn comparison_sort<T: Channel>(data: &mut [T], width: usize, height: usize, pipeline: &Pipeline) {
+    for stage in pipeline.stages.iter().filter(|stage| !stage.muted) {
+        let single = Pipeline {
+            stages: vec![stage.clone()],
+        };
+        let intervals = stage_intervals(data, width, height, &single, 0, None);
+        let lines: Vec<Vec<usize>> = match stage.direction {
+            SortDirection::Horizontal => (0..height)
+                .map(|y| (y * width..(y + 1) * width).collect())
+                .collect(),
+            SortDirection::Vertical => (0..width)
+                .map(|x| (0..height).map(|y| x + y * width).collect())
+                .collect(),
+            _ => unimplemented!("only rows and columns"),
+        };
+        let interval = |i: usize| match intervals[i] {
+            IntervalPixel::Matched(n) | IntervalPixel::Added(n) => Some(n),
+            IntervalPixel::Unsorted => None,
+        };
+        for line in lines {
+            let mut row: Vec<[T; 4]> = line
+                .iter()
+                .map(|&i| data[i * 4..i * 4 + 4].try_into().unwrap())
+                .collect();
+            let mut start = 0;
+            while start < line.len() {
+                let current = interval(line[start]);
+                let end = start
+                    + line[start..]
+                        .iter()
+                        .take_while(|&&i| interval(i) == current)
+                        .count();
+                if current.is_some() {
+                    let mut sorted = row[start..end].to_vec();
+                    sorted.sort_unstable_by(|a, b| {
+                        stage.ordering.key(a).total_cmp(&stage.ordering.key(b))
+                    });
+                    if stage.ordering_reverse {
+                        sorted.reverse();
+                    }
+                    row[start..end].copy_from_slice(&sorted);
+                }
+                start = end;
+            }
+            for (&i, pixel) in line.iter().zip(&row) {
+                data[i * 4..i * 4 + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+}
+
+// Shortest time of a few sorts of a copy of the data.
+fn time<T: Channel>(
+    data: &[T],
+    pipeline: &Pipeline,
+    sort: fn(&mut [T], usize, usize, &Pipeline),
+) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut data = data.to_vec();
+            let start = Instant::now();
+            sort(&mut data, WIDTH, HEIGHT, pipeline);
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    // One thread, so the times compare between machines with any number of cores
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build_global()
+        .expect("the thread pool is only built once");
+    let bytes = image();
+    let words: Vec<u16> = bytes.iter().map(|&c| c as u16 * 257).collect();
+    let pipelines = [
+        ("luminance:150", "luminance", "horizontal"),
+        ("luminance:150", "luminance", "vertical"),
+        ("luminance:200", "hue", "horizontal"),
+        ("fixed:256", "red", "horizontal"),
+        ("random:64:1", "color:ff8000:ciede2000", "horizontal"),
+        ("luminance:150", "lightness", "angle:30"),
+    ];
+    println!(
+        "{}x{} pixels, fastest of {} runs on one thread",
+        WIDTH, HEIGHT, RUNS
+    );
+    let pipelines: Vec<(&str, &str, &str, Pipeline)> = pipelines
+        .into_iter()
+        .map(|(threshold, ordering, direction)| {
+            let stage = Stage {
+                threshold: threshold.parse().unwrap(),
+                ordering: ordering.parse().unwrap(),
+                direction: direction.parse().unwrap(),
+                ..Stage::default()
+            };
+            let pipeline = Pipeline {
+                stages: vec![stage],
+            };
+            (threshold, ordering, direction, pipeline)
+        })
+        .collect();
+    for baseline in [false, true] {
+        println!();
+        println!(
+            "{}",
+            match baseline {
+                false => "sort_image",
+                true => "comparison sort (baseline)",
+            }
+        );
+        for (threshold, ordering, direction, pipeline) in &pipelines {
+            let times = match baseline {
+                false => (
+                    time(&bytes, pipeline, sort_image),
+                    time(&words, pipeline, sort_image),
+                ),
+                true if pipeline.stages.iter().all(|stage| {
+                    matches!(
+                        stage.direction,
+                        SortDirection::Horizontal | SortDirection::Vertical
+                    )
+                }) =>
+                {
+                    (
+                        time(&bytes, pipeline, comparison_sort),
+                        time(&words, pipeline, comparison_sort),
+                    )
+                }
+                true => continue,
+            };
+            println!(
+                "{:<14} {:<24} {:<11} 8-bit {:>8.1?}  16-bit {:>8.1?}",
+                threshold, ordering, direction, times.0, times.1,
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+/// Type of the channels of image data, 4 of which make up a pixel: red, green, blue and alpha.
+///
+/// Thresholds and orderings see every channel scaled to 0-255, so they work the same at any bit
+/// depth while the pixels are sorted at full precision.
+pub trait Channel: Copy + Send + Sync {
+    /// The value of the channel scaled to 0-255.
+    fn to_f32(self) -> f32;
+
+    /// The value of the channel if it is 8-bit. Orderings of 8-bit pixels which only take a few
+    /// different values are sorted by counting them.
+    fn to_u8(self) -> Option<u8> {
+        None
+    }
+}
+
+impl Channel for u8 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn to_u8(self) -> Option<u8> {
+        Some(self)
+    }
+}
+
+impl Channel for u16 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 257.
+    }
+}
+
+/// Float channels go from 0 to 1.
+impl Channel for f32 {
+    fn to_f32(self) -> f32 {
+        self * 255.
+    }
+}
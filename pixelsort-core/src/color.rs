@@ -0,0 +1,401 @@
+use crate::Channel;
+
+// get pixel Luminance (0-255)
+pub(crate) fn pixel_to_luminance<T: Channel>(pixel: &[T; 4]) -> f32 {
+    (pixel[0].to_f32() * 2. + pixel[1].to_f32() * 3. + pixel[2].to_f32()) / 6.
+}
+
+// get pixel hue (0-360), saturation and value (0-1) in the HSV colour model
+pub(crate) fn pixel_to_hsv<T: Channel>(pixel: &[T; 4]) -> [f32; 3] {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c.to_f32() / 255.);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    let saturation = if max == 0. { 0. } else { delta / max };
+    [hue, saturation, max]
+}
+
+/// Metrics to measure the distance between two colours with.
+#[derive(
+    Default, strum_macros::Display, strum_macros::EnumIter, PartialEq, Eq, Clone, Copy, Debug,
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DistanceMetric {
+    /// Straight line distance between the sRGB values, 0 to 442.
+    Euclidean,
+    /// Weighted sRGB distance which is closer to perception, 0 to 765.
+    ///
+    /// See <https://www.compuphase.com/cmetric.htm>
+    #[default]
+    Redmean,
+    /// Straight line distance in the CIELAB colour space, 0 to about 260.
+    Cie76,
+    /// The CIEDE2000 perceptual difference in the CIELAB colour space, 0 to about 120.
+    Ciede2000,
+    /// Straight line distance in the Oklab colour space, 0 to about 1.
+    Oklab,
+}
+
+impl DistanceMetric {
+    /// The largest distance this metric reports, for the range of thresholds.
+    pub fn max_distance(&self) -> f32 {
+        match self {
+            DistanceMetric::Euclidean => 442.,
+            DistanceMetric::Redmean => 765.,
+            DistanceMetric::Cie76 => 260.,
+            DistanceMetric::Ciede2000 => 120.,
+            DistanceMetric::Oklab => 1.,
+        }
+    }
+
+    /// Distance between the colour of a pixel and another colour.
+    pub fn distance<T: Channel>(&self, pixel: &[T; 4], color: &[u8; 3]) -> f32 {
+        let pixel = [pixel[0], pixel[1], pixel[2]].map(Channel::to_f32);
+        let color = &color.map(|c| c as f32);
+        match self {
+            DistanceMetric::Euclidean => {
+                let [r, g, b] = channel_differences(&pixel, color);
+                (r * r + g * g + b * b).sqrt()
+            }
+            DistanceMetric::Redmean => {
+                // source: https://www.compuphase.com/cmetric.htm
+                let rmean = (pixel[0] + color[0]) / 2.;
+                let [r, g, b] = channel_differences(&pixel, color);
+                (((512. + rmean) * r * r) / 256. + 4. * g * g + ((767. - rmean) * b * b) / 256.)
+                    .sqrt()
+            }
+            DistanceMetric::Cie76 => euclidean(srgb_to_lab(&pixel), srgb_to_lab(color)),
+            DistanceMetric::Ciede2000 => ciede2000(srgb_to_lab(&pixel), srgb_to_lab(color)),
+            DistanceMetric::Oklab => euclidean(srgb_to_oklab(&pixel), srgb_to_oklab(color)),
+        }
+    }
+}
+
+// Parse distance metrics from the command line
+impl std::str::FromStr for DistanceMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "euclidean" => Ok(DistanceMetric::Euclidean),
+            "redmean" => Ok(DistanceMetric::Redmean),
+            "cie76" => Ok(DistanceMetric::Cie76),
+            "ciede2000" => Ok(DistanceMetric::Ciede2000),
+            "oklab" => Ok(DistanceMetric::Oklab),
+            _ => Err(format!(
+                "Unknown distance metric \"{}\", expected \"euclidean\", \"redmean\", \"cie76\", \"ciede2000\" or \"oklab\".",
+                s
+            )),
+        }
+    }
+}
+
+fn channel_differences(a: &[f32; 3], b: &[f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] - b[i])
+}
+
+fn euclidean(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+// sRGB channel (0-255) to linear light (0-1)
+fn srgb_to_linear(channel: f32) -> f32 {
+    let c = channel / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// sRGB to CIELAB, using the D65 white point
+fn srgb_to_lab(color: &[f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(srgb_to_linear);
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 216. / 24389. {
+            t.cbrt()
+        } else {
+            (24389. / 27. * t + 16.) / 116.
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+// sRGB to Oklab, source: https://bottosson.github.io/posts/oklab/
+fn srgb_to_oklab(color: &[f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(srgb_to_linear);
+    let l = (0.41222146 * r + 0.53633255 * g + 0.051445995 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+// CIEDE2000 colour difference, source: http://www2.ece.rochester.edu/~gsharma/ciede2000/
+fn ciede2000(lab1: [f32; 3], lab2: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+
+    let c_mean = ((a1.hypot(b1)) + (a2.hypot(b2))) / 2.;
+    let g = 0.5 * (1. - (c_mean.powi(7) / (c_mean.powi(7) + 25f32.powi(7))).sqrt());
+    let a1 = a1 * (1. + g);
+    let a2 = a2 * (1. + g);
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let hue = |b: f32, a: f32| {
+        if a == 0. && b == 0. {
+            0.
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.)
+        }
+    };
+    let h1 = hue(b1, a1);
+    let h2 = hue(b2, a2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0. {
+        0.
+    } else if (h2 - h1).abs() <= 180. {
+        h2 - h1
+    } else if h2 - h1 > 180. {
+        h2 - h1 - 360.
+    } else {
+        h2 - h1 + 360.
+    };
+    let delta_h = 2. * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.).sin();
+
+    let l_mean = (l1 + l2) / 2.;
+    let c_mean = (c1 + c2) / 2.;
+    let h_mean = if c1 * c2 == 0. {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180. {
+        (h1 + h2) / 2.
+    } else if h1 + h2 < 360. {
+        (h1 + h2 + 360.) / 2.
+    } else {
+        (h1 + h2 - 360.) / 2.
+    };
+
+    let t = 1. - 0.17 * (h_mean - 30.).to_radians().cos()
+        + 0.24 * (2. * h_mean).to_radians().cos()
+        + 0.32 * (3. * h_mean + 6.).to_radians().cos()
+        - 0.20 * (4. * h_mean - 63.).to_radians().cos();
+    let delta_theta = 30. * (-((h_mean - 275.) / 25.).powi(2)).exp();
+    let r_c = 2. * (c_mean.powi(7) / (c_mean.powi(7) + 25f32.powi(7))).sqrt();
+    let s_l = 1. + (0.015 * (l_mean - 50.).powi(2)) / (20. + (l_mean - 50.).powi(2)).sqrt();
+    let s_c = 1. + 0.045 * c_mean;
+    let s_h = 1. + 0.015 * c_mean * t;
+    let r_t = -(2. * delta_theta).to_radians().sin() * r_c;
+
+    ((delta_l / s_l).powi(2)
+        + (delta_c / s_c).powi(2)
+        + (delta_h / s_h).powi(2)
+        + r_t * (delta_c / s_c) * (delta_h / s_h))
+        .sqrt()
+}
+
+// Parse a hex colour like "00ff00" or "#00ff00"
+pub(crate) fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("Invalid colour \"{}\", expected 6 hex digits.", s));
+    }
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid colour \"{}\", expected 6 hex digits.", s))?;
+    }
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{:?} is not {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    // Test data from Sharma, Wu and Dalal, "The CIEDE2000 Color-Difference Formula:
+    // Implementation Notes, Supplementary Test Data, and Mathematical Observations"
+    #[test]
+    fn ciede2000_reference_pairs() {
+        let pairs: [([f32; 3], [f32; 3], f32); 34] = [
+            ([50., 2.6772, -79.7751], [50., 0., -82.7485], 2.0425),
+            ([50., 3.1571, -77.2803], [50., 0., -82.7485], 2.8615),
+            ([50., 2.8361, -74.02], [50., 0., -82.7485], 3.4412),
+            ([50., -1.3802, -84.2814], [50., 0., -82.7485], 1.),
+            ([50., -1.1848, -84.8006], [50., 0., -82.7485], 1.),
+            ([50., -0.9009, -85.5211], [50., 0., -82.7485], 1.),
+            ([50., 0., 0.], [50., -1., 2.], 2.3669),
+            ([50., -1., 2.], [50., 0., 0.], 2.3669),
+            ([50., 2.49, -0.001], [50., -2.49, 0.0009], 7.1792),
+            ([50., 2.49, -0.001], [50., -2.49, 0.001], 7.1792),
+            ([50., 2.49, -0.001], [50., -2.49, 0.0011], 7.2195),
+            ([50., 2.49, -0.001], [50., -2.49, 0.0012], 7.2195),
+            ([50., -0.001, 2.49], [50., 0.0009, -2.49], 4.8045),
+            ([50., -0.001, 2.49], [50., 0.001, -2.49], 4.8045),
+            ([50., -0.001, 2.49], [50., 0.0011, -2.49], 4.7461),
+            ([50., 2.5, 0.], [50., 0., -2.5], 4.3065),
+            ([50., 2.5, 0.], [73., 25., -18.], 27.1492),
+            ([50., 2.5, 0.], [61., -5., 29.], 22.8977),
+            ([50., 2.5, 0.], [56., -27., -3.], 31.903),
+            ([50., 2.5, 0.], [58., 24., 15.], 19.4535),
+            ([50., 2.5, 0.], [50., 3.1736, 0.5854], 1.),
+            ([50., 2.5, 0.], [50., 3.2972, 0.], 1.),
+            ([50., 2.5, 0.], [50., 1.8634, 0.5757], 1.),
+            ([50., 2.5, 0.], [50., 3.2592, 0.335], 1.),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            (
+                [63.0109, -31.0961, -5.8663],
+                [62.8187, -29.7946, -4.0864],
+                1.263,
+            ),
+            ([61.2901, 3.7196, -5.3901], [61.4292, 2.248, -4.962], 1.8731),
+            (
+                [35.0831, -44.1164, 3.7933],
+                [35.0232, -40.0716, 1.5901],
+                1.8645,
+            ),
+            (
+                [22.7233, 20.0904, -46.694],
+                [23.0331, 14.973, -42.5619],
+                2.0373,
+            ),
+            (
+                [36.4612, 47.858, 18.3852],
+                [36.2715, 50.5065, 21.2231],
+                1.4146,
+            ),
+            (
+                [90.8027, -2.0831, 1.441],
+                [91.1528, -1.6435, 0.0447],
+                1.4441,
+            ),
+            (
+                [90.9257, -0.5406, -0.9208],
+                [88.6381, -0.8985, -0.7239],
+                1.5381,
+            ),
+            (
+                [6.7747, -0.2908, -2.4247],
+                [5.8714, -0.0985, -2.2286],
+                0.6377,
+            ),
+            ([2.0776, 0.0795, -1.135], [0.9033, -0.0636, -0.5514], 0.9082),
+        ];
+        for (lab1, lab2, expected) in pairs {
+            for difference in [ciede2000(lab1, lab2), ciede2000(lab2, lab1)] {
+                assert!(
+                    (difference - expected).abs() < 1e-3,
+                    "{:?} and {:?} are {} apart instead of {}",
+                    lab1,
+                    lab2,
+                    difference,
+                    expected
+                );
+            }
+        }
+    }
+
+    // The rounded matrix is a few hundredths off the exact values
+    #[test]
+    fn lab_values() {
+        assert_close(srgb_to_lab(&[0., 0., 0.]), [0., 0., 0.], 1e-3);
+        assert_close(srgb_to_lab(&[255., 255., 255.]), [100., 0., 0.], 5e-2);
+        assert_close(srgb_to_lab(&[255., 0., 0.]), [53.24, 80.09, 67.2], 5e-2);
+    }
+
+    // Values from https://bottosson.github.io/posts/oklab/
+    #[test]
+    fn oklab_values() {
+        assert_close(srgb_to_oklab(&[0., 0., 0.]), [0., 0., 0.], 1e-4);
+        assert_close(srgb_to_oklab(&[255., 255., 255.]), [1., 0., 0.], 1e-4);
+        assert_close(
+            srgb_to_oklab(&[255., 0., 0.]),
+            [0.627955, 0.224863, 0.125846],
+            1e-4,
+        );
+        assert_close(
+            srgb_to_oklab(&[0., 255., 0.]),
+            [0.86644, -0.233888, 0.179498],
+            1e-4,
+        );
+        assert_close(
+            srgb_to_oklab(&[0., 0., 255.]),
+            [0.452014, -0.032457, -0.311528],
+            1e-4,
+        );
+    }
+
+    // Black and white are within the largest distance of each metric, equal colours 0 apart
+    #[test]
+    fn max_distances() {
+        for metric in [
+            DistanceMetric::Euclidean,
+            DistanceMetric::Redmean,
+            DistanceMetric::Cie76,
+            DistanceMetric::Oklab,
+        ] {
+            let distance = metric.distance(&[0u8, 0, 0, 255], &[255, 255, 255]);
+            let max = metric.max_distance();
+            assert!(
+                distance <= max && distance > max * 0.38,
+                "{} {}",
+                metric,
+                distance
+            );
+            assert_eq!(metric.distance(&[7u8, 80, 200, 255], &[7, 80, 200]), 0.);
+        }
+    }
+
+    #[test]
+    fn parse_metrics() {
+        use strum::IntoEnumIterator;
+        for metric in DistanceMetric::iter() {
+            assert_eq!(metric.to_string().to_lowercase().parse(), Ok(metric));
+        }
+        for s in ["manhattan", "", "Oklab", "cie94"] {
+            assert!(s.parse::<DistanceMetric>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!(parse_color("00ff80"), Ok([0, 255, 128]));
+        assert_eq!(parse_color("#00FF80"), Ok([0, 255, 128]));
+        for s in ["", "0f8", "00ff8", "00ff800", "00fg80", "#0ff80"] {
+            assert!(parse_color(s).is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Follows the progress of a sort and cancels it, shared with the thread running
+/// [`sort_image_controlled`](crate::sort_image_controlled).
+#[derive(Default, Debug)]
+pub struct SortControl {
+    cancelled: AtomicBool,
+    // Stages to sort, and how many of them are done
+    stage_count: AtomicUsize,
+    stages_done: AtomicUsize,
+    // Rows or paths of the current stage, and how many of them are sorted
+    row_count: AtomicUsize,
+    rows_done: AtomicUsize,
+}
+
+impl SortControl {
+    /// Stop the sort as soon as possible, leaving the image partly sorted.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the sort was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// How much of the image is sorted, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        let stage_count = self.stage_count.load(Ordering::Relaxed);
+        if stage_count == 0 {
+            return 0.;
+        }
+        let row_count = self.row_count.load(Ordering::Relaxed);
+        let stage = if row_count == 0 {
+            0.
+        } else {
+            self.rows_done.load(Ordering::Relaxed) as f32 / row_count as f32
+        };
+        let stages_done = self.stages_done.load(Ordering::Relaxed) as f32;
+        ((stages_done + stage) / stage_count as f32).min(1.)
+    }
+
+    pub(crate) fn start(&self, stage_count: usize) {
+        self.stage_count.store(stage_count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn start_stage(&self, row_count: usize) {
+        self.rows_done.store(0, Ordering::Relaxed);
+        self.row_count.store(row_count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish_row(&self) {
+        self.rows_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish_stage(&self) {
+        self.row_count.store(0, Ordering::Relaxed);
+        self.stages_done.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,142 @@
+use rayon::prelude::*;
+
+use crate::{color::pixel_to_luminance, Channel};
+
+// Luminance of every pixel of a RGBA image.
+pub(crate) fn luminance_map<T: Channel>(data: &[T]) -> Vec<f32> {
+    data.as_chunks::<4>()
+        .0
+        .par_iter()
+        .map(pixel_to_luminance)
+        .collect()
+}
+
+// Average every value with its neighbours up to the radius away, edges are clamped.
+pub(crate) fn box_blur<const N: usize>(
+    values: &[[f32; N]],
+    width: usize,
+    height: usize,
+    radius: usize,
+) -> Vec<[f32; N]> {
+    if radius == 0 {
+        return values.to_vec();
+    }
+    let radius = radius as isize;
+    let blur = |values: &[[f32; N]], step: (isize, isize)| -> Vec<[f32; N]> {
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                let mut sum = [0.; N];
+                for offset in -radius..=radius {
+                    let x = (x + offset * step.0).clamp(0, width as isize - 1) as usize;
+                    let y = (y + offset * step.1).clamp(0, height as isize - 1) as usize;
+                    for (sum, value) in sum.iter_mut().zip(values[x + y * width]) {
+                        *sum += value;
+                    }
+                }
+                sum.map(|s| s / (2 * radius + 1) as f32)
+            })
+            .collect()
+    };
+    blur(&blur(values, (1, 0)), (0, 1))
+}
+
+// Horizontal and vertical Sobel gradient of a single channel image, edges are clamped.
+pub(crate) fn sobel(values: &[f32], width: usize, height: usize) -> Vec<[f32; 2]> {
+    let at = |x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        values[x + y * width]
+    };
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let gx = at(x + 1, y - 1) + 2. * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2. * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2. * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2. * at(x, y - 1)
+                - at(x + 1, y - 1);
+            [gx, gy]
+        })
+        .collect()
+}
+
+// Thin edges of a RGBA image, found like Canny without the hysteresis: the luminance is
+// blurred by the radius, and pixels whose gradient is steeper than its neighbours across the
+// edge and above the limit set by the sensitivity (0-1) are edges.
+pub(crate) fn edge_map<T: Channel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    sensitivity: f32,
+    blur: f32,
+) -> Vec<bool> {
+    let luminance: Vec<[f32; 1]> = luminance_map(data).into_iter().map(|l| [l]).collect();
+    let luminance: Vec<f32> = box_blur(&luminance, width, height, blur.max(0.).round() as usize)
+        .into_iter()
+        .map(|[l]| l)
+        .collect();
+    let gradient = sobel(&luminance, width, height);
+    // Steepness in luminance per pixel, the sobel kernel weighs 4 pixels on each side
+    let magnitude: Vec<f32> = gradient.iter().map(|[gx, gy]| gx.hypot(*gy) / 4.).collect();
+    let limit = 255. * (1. - sensitivity.clamp(0., 1.));
+    let at = |x: isize, y: isize| {
+        if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+            0.
+        } else {
+            magnitude[x as usize + y as usize * width]
+        }
+    };
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let value = magnitude[i];
+            if value <= limit || value == 0. {
+                return false;
+            }
+            // Compare with the neighbours along the gradient, rounded to 45 degrees
+            let [gx, gy] = gradient[i];
+            let octant = (gy.atan2(gx).to_degrees().rem_euclid(180.) / 45.).round() as usize % 4;
+            let (dx, dy) = [(1, 0), (1, 1), (0, 1), (-1, 1)][octant];
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            value >= at(x + dx, y + dy) && value >= at(x - dx, y - dy)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A black left half next to a white right half
+    fn step_image(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| match i % width < width / 2 {
+                true => [0, 0, 0, 255],
+                false => [255; 4],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edges_of_a_step() {
+        let (width, height) = (8, 5);
+        let data = step_image(width, height);
+        let step: Vec<bool> = (0..width * height)
+            .map(|i| i % width == 3 || i % width == 4)
+            .collect();
+        // Blurring spreads the gradient, which is thinned back to the step
+        for blur in [0., 1.] {
+            assert_eq!(edge_map(&data, width, height, 0.5, blur), step);
+        }
+        // Not even the step is steep enough without sensitivity
+        assert!(!edge_map(&data, width, height, 0., 0.).contains(&true));
+        // A flat image has no edges however sensitive
+        assert!(!edge_map(&[90u8; 8 * 5 * 4], width, height, 1., 0.).contains(&true));
+    }
+}
@@ -0,0 +1,231 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::{
+    filter::{box_blur, luminance_map, sobel},
+    paths::Path,
+    Channel,
+};
+
+// Angle (radians, clockwise from left to right) of the edges at every pixel. The gradients are
+// smoothed as a structure tensor so the edge direction is stable, flat areas go left to right.
+pub(crate) fn edge_angles<T: Channel>(data: &[T], width: usize, height: usize) -> Vec<f32> {
+    let tensor: Vec<[f32; 3]> = sobel(&luminance_map(data), width, height)
+        .into_iter()
+        .map(|[gx, gy]| [gx * gx, gx * gy, gy * gy])
+        .collect();
+    let tensor = box_blur(&tensor, width, height, 2);
+    tensor
+        .into_iter()
+        .map(|[xx, xy, yy]| {
+            if xx + yy < 1. {
+                0.
+            } else {
+                // Direction of the strongest gradient, edges run across it
+                0.5 * (2. * xy).atan2(xx - yy) + PI / 2.
+            }
+        })
+        .collect()
+}
+
+// Angle (radians) of a seeded Perlin noise field at every pixel. `scale` is the size of the
+// noise features in pixels, `strength` how far the angle turns, in half turns.
+pub(crate) fn noise_angles(
+    seed: u32,
+    scale: f32,
+    strength: f32,
+    width: usize,
+    height: usize,
+) -> Vec<f32> {
+    let scale = scale.max(1.);
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+            perlin(x / scale, y / scale, seed) * strength * PI
+        })
+        .collect()
+}
+
+// Integer hash of a lattice point
+fn hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+// 2D gradient noise, roughly from -1 to 1
+fn perlin(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    // Dot product of the random gradient at a lattice corner with the offset to it
+    let corner = |cx: f32, cy: f32| {
+        let angle =
+            hash(x0 as i32 + cx as i32, y0 as i32 + cy as i32, seed) as f32 / u32::MAX as f32 * TAU;
+        let (sin, cos) = angle.sin_cos();
+        (fx - cx) * cos + (fy - cy) * sin
+    };
+    let fade = |t: f32| t * t * t * (t * (t * 6. - 15.) + 10.);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let (u, v) = (fade(fx), fade(fy));
+    let top = lerp(corner(0., 0.), corner(1., 0.), u);
+    let bottom = lerp(corner(0., 1.), corner(1., 1.), u);
+    lerp(top, bottom, v) * std::f32::consts::SQRT_2
+}
+
+// Split the image into streamlines following the angles of the field, which only matter up to
+// a half turn. Every pixel belongs to exactly one streamline, which run left to right where
+// possible.
+pub(crate) fn flow_paths(angles: &[f32], width: usize, height: usize) -> Vec<Path> {
+    let mut visited = vec![false; width * height];
+    let mut paths = vec![];
+    for start in 0..width * height {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let (sin, cos) = angles[start].sin_cos();
+        // Run left to right, or top to bottom for vertical lines
+        let direction = if cos.abs() > 1e-3 {
+            (cos.signum() * cos, cos.signum() * sin)
+        } else {
+            (0., sin.abs())
+        };
+        let backward = trace(
+            start,
+            (-direction.0, -direction.1),
+            angles,
+            &mut visited,
+            width,
+            height,
+        );
+        let forward = trace(start, direction, angles, &mut visited, width, height);
+        let mut path = backward;
+        path.reverse();
+        path.push(start);
+        path.extend(forward);
+        paths.push(path);
+    }
+    paths
+}
+
+// Follow the field from the centre of a pixel, collecting the unvisited pixels passed through
+// until leaving the image or reaching a visited one.
+fn trace(
+    start: usize,
+    mut direction: (f32, f32),
+    angles: &[f32],
+    visited: &mut [bool],
+    width: usize,
+    height: usize,
+) -> Vec<usize> {
+    const STEP: f32 = 0.5;
+    let mut position = ((start % width) as f32 + 0.5, (start / width) as f32 + 0.5);
+    let mut current = start;
+    let mut steps_in_pixel = 0;
+    let mut path = vec![];
+    loop {
+        position = (
+            position.0 + direction.0 * STEP,
+            position.1 + direction.1 * STEP,
+        );
+        if position.0 < 0.
+            || position.1 < 0.
+            || position.0 >= width as f32
+            || position.1 >= height as f32
+        {
+            break;
+        }
+        let pixel = position.0 as usize + position.1 as usize * width;
+        if pixel == current {
+            // Stop turning in circles inside a single pixel
+            steps_in_pixel += 1;
+            if steps_in_pixel > 4 {
+                break;
+            }
+            continue;
+        }
+        if visited[pixel] {
+            break;
+        }
+        visited[pixel] = true;
+        path.push(pixel);
+        current = pixel;
+        steps_in_pixel = 0;
+
+        // Keep going the same way, the field has no sense of forwards
+        let (sin, cos) = angles[pixel].sin_cos();
+        direction = if cos * direction.0 + sin * direction.1 < 0. {
+            (-cos, -sin)
+        } else {
+            (cos, sin)
+        };
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{paths::tests::assert_partition, random::Rng};
+
+    const SIZES: [(usize, usize); 4] = [(1, 1), (9, 1), (1, 7), (31, 19)];
+
+    #[test]
+    fn uniform_fields_partition() {
+        for (width, height) in SIZES {
+            for angle in [0., 0.3, PI / 4., PI / 2., PI, -2.] {
+                let angles = vec![angle; width * height];
+                assert_partition(&flow_paths(&angles, width, height), width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_fields_partition() {
+        for (width, height) in SIZES {
+            for (seed, scale, strength) in [(0, 8., 1.), (5, 1., 3.), (9, 40., 0.2)] {
+                let angles = noise_angles(seed, scale, strength, width, height);
+                assert_partition(&flow_paths(&angles, width, height), width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_fields_partition() {
+        let mut rng = Rng::new(4, 0);
+        for (width, height) in SIZES {
+            let data: Vec<u8> = (0..width * height * 4)
+                .map(|_| rng.next_u64() as u8)
+                .collect();
+            let angles = edge_angles(&data, width, height);
+            assert_partition(&flow_paths(&angles, width, height), width, height);
+        }
+    }
+
+    // Rows of a field running left to right are single streamlines
+    #[test]
+    fn horizontal_field_follows_rows() {
+        let paths = flow_paths(&[0.; 5 * 3], 5, 3);
+        assert_eq!(
+            paths,
+            [
+                vec![0, 1, 2, 3, 4],
+                vec![5, 6, 7, 8, 9],
+                vec![10, 11, 12, 13, 14]
+            ]
+        );
+    }
+
+    #[test]
+    fn noise_is_reproducible() {
+        let noise = |seed| noise_angles(seed, 6., 1., 20, 10);
+        assert_eq!(noise(3), noise(3));
+        assert_ne!(noise(3), noise(4));
+        // Within the turn set by the strength
+        assert!(noise_angles(7, 3., 0.5, 20, 10)
+            .iter()
+            .all(|angle| angle.abs() <= 0.5 * PI * 1.01));
+    }
+}
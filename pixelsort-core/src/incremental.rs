@@ -0,0 +1,494 @@
+use rayon::prelude::*;
+
+use crate::{
+    check_sizes, paths::Path, reverse_and_mask, sort_paths, sort_row, sort_stage,
+    sort_stage_matched, stage_paths, write_paths, Channel, Pipeline, RowScratch, SortControl,
+    SortDirection, Stage, Threshold,
+};
+
+/// Results of the last sort of an image with [`SortCache::sort`], which let the next sort of it
+/// skip the work whose result did not change.
+///
+/// After a part of the source image or mask changed only the rows or paths through the changed
+/// pixels are sorted again, and the stages before the first one whose settings changed are not
+/// sorted at all. The cache keeps a copy of the image between each two stages, and the edges
+/// found in the input of each stage with an edges threshold for as long as that input stays the
+/// same.
+pub struct SortCache<T> {
+    width: usize,
+    height: usize,
+    // Revision of the source of the last sort
+    revision: usize,
+    // Unmuted stages of the last sort, None if there was none or it was cancelled
+    stages: Option<Vec<Stage>>,
+    // The image as it entered each of the stages after the first
+    inputs: Vec<Vec<T>>,
+    // The edges threshold of each stage and the pixels it matched in the input of the stage, if
+    // the input did not change since
+    edges: Vec<Option<(Threshold, Vec<bool>)>>,
+}
+
+impl<T> Default for SortCache<T> {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            revision: 0,
+            stages: None,
+            inputs: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+}
+
+impl<T: Channel> SortCache<T> {
+    /// Sort the source image into `dest` like
+    /// [`sort_image_controlled`](crate::sort_image_controlled) sorts it in place.
+    ///
+    /// `dest` must still hold the result of the last sort with the cache, and `changed` holds
+    /// whether each pixel of the source or the mask changed since then, row by row. Everything
+    /// is sorted if `changed` is None, or the last sort was of a different size. `revision`
+    /// identifies the source, it must be different from the last one whenever the source
+    /// changed.
+    ///
+    /// Returns whether the image was sorted completely. A cancelled sort leaves `dest` partly
+    /// sorted, and the next sort sorts everything.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `source`, `dest`, `mask` or `changed` does not match the width and
+    /// height.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sort(
+        &mut self,
+        source: &[T],
+        revision: usize,
+        dest: &mut [T],
+        width: usize,
+        height: usize,
+        pipeline: &Pipeline,
+        mask: Option<&[bool]>,
+        changed: Option<&[bool]>,
+        control: &SortControl,
+    ) -> bool {
+        check_sizes(source, width, height, mask);
+        assert_eq!(
+            dest.len(),
+            source.len(),
+            "dest does not match the image size"
+        );
+        if let Some(changed) = changed {
+            assert_eq!(
+                changed.len(),
+                width * height,
+                "changed pixels do not match the image size"
+            );
+        }
+        let stages: Vec<Stage> = pipeline
+            .stages
+            .iter()
+            .filter(|stage| !stage.muted)
+            .cloned()
+            .collect();
+
+        // Stages with the same settings as last time, whose results still hold where nothing
+        // changed
+        let same_size = (self.width, self.height) == (width, height);
+        // Whether the input of the next stage is the same as in the last sort
+        let mut input_unchanged = self.stages.is_some() && same_size && self.revision == revision;
+        let reused = match self.stages.take().filter(|_| same_size) {
+            Some(previous) => {
+                let same = previous
+                    .iter()
+                    .zip(&stages)
+                    .take_while(|(previous, stage)| previous == stage)
+                    .count();
+                // The result of the last stage is in dest rather than the copy the next stage
+                // starts from
+                if previous.len() == stages.len() {
+                    same
+                } else {
+                    same.min(previous.len().min(stages.len()).saturating_sub(1))
+                }
+            }
+            None => 0,
+        };
+        if !same_size {
+            self.inputs.clear();
+        }
+        self.width = width;
+        self.height = height;
+        self.revision = revision;
+        self.edges.resize(stages.len(), None);
+        self.inputs.truncate(stages.len().saturating_sub(1));
+        while self.inputs.len() + 1 < stages.len() {
+            self.inputs.push(source.to_vec());
+        }
+
+        control.start(stages.len());
+        if stages.is_empty() || width == 0 || height == 0 {
+            dest.copy_from_slice(source);
+            self.stages = Some(stages);
+            return true;
+        }
+        // Pixels whose input or mask changed, None if every pixel did
+        let mut dirty = changed.map(<[bool]>::to_vec);
+        for (index, stage) in stages.iter().enumerate() {
+            if control.is_cancelled() {
+                return false;
+            }
+            if index >= reused {
+                dirty = None;
+            }
+            if !input_unchanged {
+                self.edges[index] = None;
+            }
+            // A stage with nothing to sort again leaves its result, the input of the next stage,
+            // as it was
+            if dirty.as_ref().is_some_and(|pixels| !pixels.contains(&true)) {
+                control.finish_stage();
+                continue;
+            }
+            let (before, after) = self.inputs.split_at_mut(index);
+            let input = match index.checked_sub(1) {
+                Some(previous) => &before[previous][..],
+                None => source,
+            };
+            let output = match after.first_mut() {
+                Some(next) => &mut next[..],
+                None => &mut *dest,
+            };
+            let edges = match stage.threshold {
+                Threshold::Edges(..) => Some(edge_matches(
+                    &mut self.edges[index],
+                    input,
+                    width,
+                    height,
+                    &stage.threshold,
+                )),
+                _ => None,
+            };
+            sort_stage_dirty(
+                input,
+                output,
+                width,
+                height,
+                stage,
+                mask,
+                edges,
+                input_unchanged,
+                &mut dirty,
+                control,
+            );
+            input_unchanged = false;
+            control.finish_stage();
+        }
+        if control.is_cancelled() {
+            return false;
+        }
+        self.stages = Some(stages);
+        true
+    }
+}
+
+// The pixels an edges threshold matches in the input of a stage, kept in the cache of the stage
+// while the input and threshold stay the same
+fn edge_matches<'a, T: Channel>(
+    cache: &'a mut Option<(Threshold, Vec<bool>)>,
+    input: &[T],
+    width: usize,
+    height: usize,
+    threshold: &Threshold,
+) -> &'a [bool] {
+    if cache.as_ref().is_none_or(|(cached, _)| cached != threshold) {
+        *cache = Some((
+            threshold.clone(),
+            threshold.match_image(input, width, height),
+        ));
+    }
+    &cache.as_ref().unwrap().1
+}
+
+// Sort the input of a stage into the output, which holds the result of the stage from the last
+// sort. Only the rows or paths through dirty pixels are sorted again, and all of their pixels
+// become dirty for the next stage. Without dirty pixels everything is sorted. `edges` holds the
+// pixels matched by an edges threshold.
+#[allow(clippy::too_many_arguments)]
+fn sort_stage_dirty<T: Channel>(
+    input: &[T],
+    output: &mut [T],
+    width: usize,
+    height: usize,
+    stage: &Stage,
+    mask: Option<&[bool]>,
+    edges: Option<&[bool]>,
+    input_unchanged: bool,
+    dirty: &mut Option<Vec<bool>>,
+    control: &SortControl,
+) {
+    // Edges depend on the neighbouring pixels, so a change of the input can move them anywhere
+    if !input_unchanged
+        && (matches!(stage.threshold, Threshold::Edges(..))
+            || stage.direction == SortDirection::Edges)
+    {
+        *dirty = None;
+    }
+    let Some(pixels) = dirty.as_mut() else {
+        output.copy_from_slice(input);
+        match edges {
+            Some(edges) => {
+                let matched = reverse_and_mask(edges.to_vec(), stage, mask);
+                sort_stage_matched(output, width, height, stage, &matched, mask, control);
+            }
+            None => sort_stage(output, width, height, stage, mask, control),
+        }
+        return;
+    };
+
+    let input_pixels = input.as_chunks::<4>().0;
+    let reverse = stage.threshold_inverted();
+    // Whether the stage sorts the pixel, like stage_matched
+    let matched = |i: usize| {
+        let threshold = match edges {
+            Some(edges) => edges[i],
+            None => stage.threshold.matches(&input_pixels[i]),
+        };
+        (threshold != reverse) && mask.is_none_or(|mask| mask[i])
+    };
+    match stage_paths(input, width, height, stage) {
+        None => {
+            let rows: Vec<bool> = pixels
+                .par_chunks_exact(width)
+                .map(|row| row.contains(&true))
+                .collect();
+            control.start_stage(rows.iter().filter(|&&row| row).count());
+            output
+                .par_chunks_exact_mut(width * 4)
+                .zip(input.par_chunks_exact(width * 4))
+                .zip(pixels.par_chunks_exact_mut(width))
+                .enumerate()
+                .filter(|(y, _)| rows[*y])
+                .for_each_init(
+                    || (RowScratch::default(), Vec::new()),
+                    |(scratch, row_matched), (y, ((row, input), dirty))| {
+                        if control.is_cancelled() {
+                            return;
+                        }
+                        row.copy_from_slice(input);
+                        row_matched.clear();
+                        row_matched.extend((y * width..(y + 1) * width).map(matched));
+                        let allowed = mask.map(|mask| &mask[y * width..(y + 1) * width]);
+                        sort_row(
+                            row.as_chunks_mut::<4>().0,
+                            row_matched,
+                            allowed,
+                            y,
+                            stage,
+                            scratch,
+                        );
+                        dirty.fill(true);
+                        control.finish_row();
+                    },
+                );
+        }
+        Some(paths) => {
+            // Every pixel is on a path, so the dirty ones are all sorted again
+            let paths: Vec<(usize, &Path)> = paths
+                .par_iter()
+                .enumerate()
+                .filter(|(_, path)| path.iter().any(|&i| pixels[i]))
+                .collect();
+            control.start_stage(paths.len());
+            let sorted = sort_paths(input, matched, mask, &paths, stage, control);
+            write_paths(output, &paths, &sorted);
+            for &i in paths.iter().flat_map(|(_, path)| path.iter()) {
+                pixels[i] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random::Rng, sort_image_masked};
+
+    const WIDTH: usize = 67;
+    const HEIGHT: usize = 41;
+
+    fn noise_image(rng: &mut Rng) -> Vec<u16> {
+        (0..WIDTH * HEIGHT * 4)
+            .map(|_| rng.next_u64() as u16)
+            .collect()
+    }
+
+    fn stage(threshold: Threshold, direction: SortDirection) -> Stage {
+        Stage {
+            threshold,
+            direction,
+            ..Stage::default()
+        }
+    }
+
+    // Set a random rectangle of the mask, returning which pixels changed
+    fn paint(mask: &mut [bool], value: bool, rng: &mut Rng) -> Vec<bool> {
+        let mut changed = vec![false; mask.len()];
+        let x = rng.next_u64() as usize % WIDTH;
+        let y = rng.next_u64() as usize % HEIGHT;
+        for y in y..(y + 10).min(HEIGHT) {
+            for x in x..(x + 10).min(WIDTH) {
+                let i = x + y * WIDTH;
+                changed[i] = mask[i] != value;
+                mask[i] = value;
+            }
+        }
+        changed
+    }
+
+    // Sort with the cache after each edit, comparing to sorting everything again
+    fn check_edits(pipeline: &Pipeline, edit: impl Fn(usize, &mut Pipeline)) {
+        let mut rng = Rng::new(7, 0);
+        let source = noise_image(&mut rng);
+        let mut cache = SortCache::default();
+        let mut dest = vec![0; source.len()];
+        let mut mask = vec![true; WIDTH * HEIGHT];
+        let mut pipeline = pipeline.clone();
+        let control = SortControl::default();
+        assert!(cache.sort(&source, 0, &mut dest, WIDTH, HEIGHT, &pipeline, None, None, &control));
+        for round in 0..6 {
+            let changed = paint(&mut mask, round % 2 == 1, &mut rng);
+            edit(round, &mut pipeline);
+            assert!(cache.sort(
+                &source,
+                0,
+                &mut dest,
+                WIDTH,
+                HEIGHT,
+                &pipeline,
+                Some(&mask),
+                Some(&changed),
+                &control
+            ));
+            let mut expected = source.clone();
+            sort_image_masked(&mut expected, WIDTH, HEIGHT, &pipeline, Some(&mask));
+            assert!(
+                expected == dest,
+                "{:?} differs in round {}",
+                pipeline,
+                round
+            );
+        }
+    }
+
+    fn pipelines() -> Vec<Pipeline> {
+        use SortDirection::*;
+        [
+            vec![stage(Threshold::Luminance(150.), Horizontal)],
+            vec![stage(Threshold::Luminance(120.), Vertical)],
+            vec![
+                stage(Threshold::Hue(30., 200.), Angle(30.)),
+                stage(Threshold::Saturation(0.2, 0.9), Horizontal),
+            ],
+            vec![
+                stage(Threshold::Luminance(200.), Edges),
+                stage(Threshold::Edges(0.3, 2.), Circles([0.3, 0.6])),
+                stage(Threshold::Value(0.1, 0.8), Spiral([0.5, 0.5], 6.)),
+            ],
+        ]
+        .into_iter()
+        .map(|stages| Pipeline { stages })
+        .collect()
+    }
+
+    #[test]
+    fn mask_edits_match_full_sort() {
+        for pipeline in pipelines() {
+            check_edits(&pipeline, |_, _| ());
+        }
+    }
+
+    #[test]
+    fn stage_edits_match_full_sort() {
+        for pipeline in pipelines() {
+            check_edits(&pipeline, |round, pipeline| match round {
+                1 => pipeline.stages.last_mut().unwrap().ordering_reverse = true,
+                2 => pipeline.stages[0].merge_limit = 4,
+                3 => pipeline.stages.push(Stage::default()),
+                4 => pipeline.stages[0].muted = true,
+                5 => drop(pipeline.stages.remove(0)),
+                _ => (),
+            });
+        }
+    }
+
+    // Random and fixed intervals are the same however much of the image is sorted again
+    #[test]
+    fn interval_thresholds_match_full_sort() {
+        use SortDirection::*;
+        let pipeline = Pipeline {
+            stages: vec![
+                stage(Threshold::Random(8, 3), Horizontal),
+                Stage {
+                    threshold_reverse: true,
+                    ..stage(Threshold::Fixed(9), Vertical)
+                },
+                stage(Threshold::Random(5, 1), Radial([0.4, 0.5])),
+            ],
+        };
+        check_edits(&pipeline, |_, _| ());
+        check_edits(&pipeline, |round, pipeline| {
+            if round == 3 {
+                pipeline.stages[0].threshold = Threshold::Random(8, 4);
+            }
+        });
+    }
+
+    // The edges found in the input of a stage are only kept while the stages before it leave it
+    // as it was
+    #[test]
+    fn edge_threshold_edits_match_full_sort() {
+        use SortDirection::*;
+        let pipeline = Pipeline {
+            stages: vec![
+                stage(Threshold::Edges(0.4, 1.), Horizontal),
+                stage(Threshold::Edges(0.5, 0.), Vertical),
+            ],
+        };
+        check_edits(&pipeline, |round, pipeline| match round {
+            1 => pipeline.stages[1].threshold = Threshold::Luminance(100.),
+            2 => pipeline.stages[0].ordering_reverse = true,
+            3 => pipeline.stages[1].threshold = Threshold::Edges(0.5, 0.),
+            4 => pipeline.stages[0].threshold = Threshold::Edges(0.6, 1.),
+            _ => (),
+        });
+    }
+
+    // A cancelled sort leaves the cache empty, so the next one sorts everything
+    #[test]
+    fn sort_after_cancel() {
+        let source = noise_image(&mut Rng::new(3, 0));
+        let pipeline = Pipeline::default();
+        let mut cache = SortCache::default();
+        let mut dest = source.clone();
+        let cancelled = SortControl::default();
+        cancelled.cancel();
+        assert!(
+            !cache.sort(&source, 0, &mut dest, WIDTH, HEIGHT, &pipeline, None, None, &cancelled)
+        );
+        let unchanged = vec![false; WIDTH * HEIGHT];
+        assert!(cache.sort(
+            &source,
+            0,
+            &mut dest,
+            WIDTH,
+            HEIGHT,
+            &pipeline,
+            None,
+            Some(&unchanged),
+            &SortControl::default()
+        ));
+        let mut expected = source.clone();
+        sort_image_masked(&mut expected, WIDTH, HEIGHT, &pipeline, None);
+        assert!(expected == dest);
+    }
+}
@@ -0,0 +1,119 @@
+use rayon::prelude::*;
+
+use crate::{
+    check_sizes, sort_image_controlled, stage_matched, stage_paths, Channel, Pipeline, RowOp,
+    SortControl,
+};
+
+/// The part a pixel plays in the sorted intervals of a stage, see [`stage_intervals`].
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum IntervalPixel {
+    /// Left where it is.
+    #[default]
+    Unsorted,
+    /// Matched by the threshold, in the interval with this number along its row.
+    Matched(usize),
+    /// Added by extending or merging, in the interval with this number along its row.
+    Added(usize),
+}
+
+/// Find the intervals one stage of the pipeline sorts, as it sees the image after the unmuted
+/// stages before it.
+///
+/// Returns the part each of the `width * height` pixels plays, row by row. The stage is looked
+/// at even if it is muted, and the image data is left untouched.
+///
+/// # Panics
+///
+/// Panics if the length of `data` or `mask` does not match the width and height, or if there is
+/// no stage with the index.
+pub fn stage_intervals<T: Channel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    pipeline: &Pipeline,
+    stage: usize,
+    mask: Option<&[bool]>,
+) -> Vec<IntervalPixel> {
+    stage_intervals_controlled(
+        data,
+        width,
+        height,
+        pipeline,
+        stage,
+        mask,
+        &SortControl::default(),
+    )
+    .expect("never cancelled")
+}
+
+/// Find the intervals of a stage like [`stage_intervals`], reporting the progress of sorting the
+/// stages before it to the control and stopping early once it is cancelled.
+///
+/// Returns None if it was cancelled.
+///
+/// # Panics
+///
+/// Panics if the length of `data` or `mask` does not match the width and height, or if there is
+/// no stage with the index.
+pub fn stage_intervals_controlled<T: Channel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    pipeline: &Pipeline,
+    stage: usize,
+    mask: Option<&[bool]>,
+    control: &SortControl,
+) -> Option<Vec<IntervalPixel>> {
+    check_sizes(data, width, height, mask);
+    let before = Pipeline {
+        stages: pipeline.stages[..stage].to_vec(),
+    };
+    let stage = &pipeline.stages[stage];
+    let mut data = data.to_vec();
+    if !sort_image_controlled(&mut data, width, height, &before, mask, control) {
+        return None;
+    }
+
+    let mut intervals = vec![IntervalPixel::Unsorted; width * height];
+    if width == 0 || height == 0 {
+        return Some(intervals);
+    }
+    let matched = stage_matched(&data, width, height, stage, mask);
+    let paths = stage_paths(&data, width, height, stage).unwrap_or_else(|| {
+        (0..height)
+            .map(|y| (y * width..(y + 1) * width).collect())
+            .collect()
+    });
+    let rows: Vec<Vec<(usize, IntervalPixel)>> = paths
+        .par_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let matched: Vec<bool> = path.iter().map(|&i| matched[i]).collect();
+            let mut row_op = RowOp::default();
+            row_op.apply_threshold(&matched, index, stage);
+            if let Some(mask) = mask {
+                let allowed: Vec<bool> = path.iter().map(|&i| mask[i]).collect();
+                row_op.apply_mask(&allowed);
+            }
+            row_op
+                .slices
+                .iter()
+                .enumerate()
+                .flat_map(|(number, &(start, end))| (start..end).map(move |i| (number, i)))
+                .map(|(number, i)| {
+                    let part = if matched[i] {
+                        IntervalPixel::Matched(number)
+                    } else {
+                        IntervalPixel::Added(number)
+                    };
+                    (path[i], part)
+                })
+                .collect()
+        })
+        .collect();
+    for (pixel, part) in rows.into_iter().flatten() {
+        intervals[pixel] = part;
+    }
+    Some(intervals)
+}
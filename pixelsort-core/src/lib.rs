@@ -0,0 +1,658 @@
+//! Pixel sorting of RGBA image buffers.
+//!
+//! An image is sorted by a [`Pipeline`] of [`Stage`]s. Each stage uses a [`Threshold`] to
+//! find the ranges of pixels to sort along each row or column, and a [`PixelOrdering`] to
+//! sort them.
+//!
+//! ```
+//! use pixelsort_core::{sort_image, Pipeline};
+//!
+//! // A 2x1 image, a white pixel followed by a black one.
+//! let mut data: Vec<u8> = vec![255, 255, 255, 255, 0, 0, 0, 255];
+//! sort_image(&mut data, 2, 1, &Pipeline::default());
+//! ```
+//!
+//! The channels of the image data can be any [`Channel`] type, `u8` for 8-bit images, `u16` for
+//! 16-bit ones or `f32` from 0 to 1, and are sorted at their full precision.
+//!
+//! With the `serde` feature the pipeline can be serialized, for example to store presets.
+#![warn(missing_docs)]
+
+use ordering::OrderScratch;
+use rayon::prelude::*;
+
+mod channel;
+mod color;
+mod control;
+mod filter;
+mod flow;
+mod incremental;
+mod intervals;
+mod ordering;
+mod paths;
+mod random;
+mod row_op;
+mod threshold;
+
+pub use channel::Channel;
+pub use color::DistanceMetric;
+pub use control::SortControl;
+pub use incremental::SortCache;
+pub use intervals::{stage_intervals, stage_intervals_controlled, IntervalPixel};
+pub use ordering::PixelOrdering;
+pub use row_op::RowOp;
+pub use threshold::Threshold;
+
+/// Directions in which the pixels can be sorted.
+///
+/// Centres are relative to the size of the image, `[0.5, 0.5]` being the middle. Centres more
+/// than one image size outside of the image are moved to that distance.
+#[derive(Default, strum_macros::Display, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SortDirection {
+    /// Sort along the rows, left to right.
+    #[default]
+    Horizontal,
+    /// Sort along the columns, top to bottom.
+    Vertical,
+    /// Sort along parallel lines at the angle in degrees, clockwise from left to right.
+    Angle(f32),
+    /// Sort clockwise along concentric circles around the centre.
+    Circles([f32; 2]),
+    /// Sort outwards along archimedean spirals around the centre, with the distance in pixels
+    /// between the turns of a spiral.
+    Spiral([f32; 2], f32),
+    /// Sort outwards along straight spokes from the centre.
+    Radial([f32; 2]),
+    /// Sort along the edges in the image, found from its luminance gradient as it enters the
+    /// stage. Flat areas are sorted left to right.
+    Edges,
+    /// Sort along the streamlines of a Perlin noise field with the seed, the size of its
+    /// features in pixels and the strength, how far the lines turn in half turns.
+    Noise(u32, f32, f32),
+}
+
+/// All of the settings of a single sorting stage.
+#[derive(Default, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Stage {
+    /// Decides which pixels are sorted.
+    pub threshold: Threshold,
+    /// Sort the pixels not matched by the threshold instead. Ignored for thresholds which match
+    /// every pixel, see [`Threshold::is_invertible`].
+    pub threshold_reverse: bool,
+    /// Decides the order the pixels are sorted into.
+    pub ordering: PixelOrdering,
+    /// Reverse the sorted pixels.
+    pub ordering_reverse: bool,
+    /// Pixels to extend each sorted range by, towards the start of the row.
+    pub extend_threshold_left: usize,
+    /// Pixels to extend each sorted range by, towards the end of the row.
+    pub extend_threshold_right: usize,
+    /// Sorted ranges at most this many pixels apart are merged.
+    pub merge_limit: usize,
+    /// Direction of the rows which are sorted.
+    pub direction: SortDirection,
+    /// Muted stages are skipped when sorting.
+    pub muted: bool,
+}
+
+impl Stage {
+    // Whether the stage sorts the pixels not matched by the threshold
+    pub(crate) fn threshold_inverted(&self) -> bool {
+        self.threshold_reverse && self.threshold.is_invertible()
+    }
+}
+
+/// The sorting stages, applied on top of each other in order.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pipeline {
+    /// The stages, in the order they are applied.
+    pub stages: Vec<Stage>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![Stage::default()],
+        }
+    }
+}
+
+impl Pipeline {
+    /// The pipeline for a copy of the image scaled by the factor, with every length in pixels
+    /// scaled along with it, so the copy is sorted like a smaller version of the image.
+    pub fn scaled(&self, scale: f32) -> Pipeline {
+        let length = |pixels: usize| (pixels as f32 * scale).round() as usize;
+        let stages = self
+            .stages
+            .iter()
+            .map(|stage| Stage {
+                threshold: match stage.threshold {
+                    Threshold::Edges(sensitivity, blur) => {
+                        Threshold::Edges(sensitivity, blur * scale)
+                    }
+                    Threshold::Random(mean, seed) => Threshold::Random(length(mean).max(1), seed),
+                    Threshold::Fixed(fixed) => Threshold::Fixed(length(fixed).max(1)),
+                    ref threshold => threshold.clone(),
+                },
+                extend_threshold_left: length(stage.extend_threshold_left),
+                extend_threshold_right: length(stage.extend_threshold_right),
+                merge_limit: length(stage.merge_limit),
+                direction: match stage.direction {
+                    SortDirection::Spiral(center, spacing) => {
+                        SortDirection::Spiral(center, spacing * scale)
+                    }
+                    SortDirection::Noise(seed, size, strength) => {
+                        SortDirection::Noise(seed, size * scale, strength)
+                    }
+                    direction => direction,
+                },
+                ..stage.clone()
+            })
+            .collect();
+        Pipeline { stages }
+    }
+}
+
+// Parse a value of a sort direction
+fn parse_direction_value(value: &str) -> Result<f32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid direction value \"{}\".", value))
+}
+
+// Parse sort directions from the command line, e.g. "vertical", "angle:45" or "circles:0.5:0.5"
+impl std::str::FromStr for SortDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let center = |x, y| Ok::<_, String>([parse_direction_value(x)?, parse_direction_value(y)?]);
+        match parts[..] {
+            ["horizontal"] => Ok(SortDirection::Horizontal),
+            ["vertical"] => Ok(SortDirection::Vertical),
+            ["angle", angle] => Ok(SortDirection::Angle(parse_direction_value(angle)?)),
+            ["circles", x, y] => Ok(SortDirection::Circles(center(x, y)?)),
+            ["spiral", x, y, spacing] => Ok(SortDirection::Spiral(
+                center(x, y)?,
+                parse_direction_value(spacing)?,
+            )),
+            ["radial", x, y] => Ok(SortDirection::Radial(center(x, y)?)),
+            ["edges"] => Ok(SortDirection::Edges),
+            ["noise", seed, scale, strength] => Ok(SortDirection::Noise(
+                seed.parse()
+                    .map_err(|_| format!("Invalid noise seed \"{}\".", seed))?,
+                parse_direction_value(scale)?,
+                parse_direction_value(strength)?,
+            )),
+            _ => Err(format!(
+                "Unknown direction \"{}\", expected \"horizontal\", \"vertical\", \"angle:<degrees>\", \"circles:<x>:<y>\", \"spiral:<x>:<y>:<spacing>\", \"radial:<x>:<y>\", \"edges\" or \"noise:<seed>:<scale>:<strength>\".",
+                s
+            )),
+        }
+    }
+}
+
+/// Sort a RGBA image in place by applying every unmuted stage of the pipeline in order.
+///
+/// `data` holds `width * height` pixels of 4 channels each, row by row.
+///
+/// # Panics
+///
+/// Panics if the length of `data` does not match the width and height.
+pub fn sort_image<T: Channel>(data: &mut [T], width: usize, height: usize, pipeline: &Pipeline) {
+    sort_image_masked(data, width, height, pipeline, None);
+}
+
+/// Sort a RGBA image in place like [`sort_image`], but only where the mask allows it.
+///
+/// `mask` holds whether each of the `width * height` pixels may be sorted, row by row.
+///
+/// # Panics
+///
+/// Panics if the length of `data` or `mask` does not match the width and height.
+pub fn sort_image_masked<T: Channel>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    pipeline: &Pipeline,
+    mask: Option<&[bool]>,
+) {
+    sort_image_controlled(data, width, height, pipeline, mask, &SortControl::default());
+}
+
+/// Sort a RGBA image in place like [`sort_image_masked`], reporting the progress to the control
+/// and stopping early once it is cancelled.
+///
+/// Returns whether the image was sorted completely, a cancelled sort leaves it partly sorted.
+///
+/// # Panics
+///
+/// Panics if the length of `data` or `mask` does not match the width and height.
+pub fn sort_image_controlled<T: Channel>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    pipeline: &Pipeline,
+    mask: Option<&[bool]>,
+    control: &SortControl,
+) -> bool {
+    check_sizes(data, width, height, mask);
+    let stages: Vec<&Stage> = pipeline
+        .stages
+        .iter()
+        .filter(|stage| !stage.muted)
+        .collect();
+    control.start(stages.len());
+    if width == 0 || height == 0 {
+        return true;
+    }
+    for stage in stages {
+        if control.is_cancelled() {
+            return false;
+        }
+        sort_stage(data, width, height, stage, mask, control);
+        control.finish_stage();
+    }
+    !control.is_cancelled()
+}
+
+// Sort every row or path of the image in place using the stage settings
+fn sort_stage<T: Channel>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    stage: &Stage,
+    mask: Option<&[bool]>,
+    control: &SortControl,
+) {
+    let matched = stage_matched(data, width, height, stage, mask);
+    sort_stage_matched(data, width, height, stage, &matched, mask, control);
+}
+
+// Sort every row or path of the image in place like sort_stage, `matched` holds whether each
+// pixel is to be sorted.
+fn sort_stage_matched<T: Channel>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    stage: &Stage,
+    matched: &[bool],
+    mask: Option<&[bool]>,
+    control: &SortControl,
+) {
+    match stage_paths(data, width, height, stage) {
+        Some(paths) => {
+            control.start_stage(paths.len());
+            let paths: Vec<(usize, &paths::Path)> = paths.iter().enumerate().collect();
+            let sorted = sort_paths(data, |i| matched[i], mask, &paths, stage, control);
+            write_paths(data, &paths, &sorted);
+        }
+        None => {
+            control.start_stage(height);
+            sort_rows(data, matched, mask, width, stage, control);
+        }
+    }
+}
+
+// Panic if the image data or mask doesn't match the size of the image
+fn check_sizes<T>(data: &[T], width: usize, height: usize, mask: Option<&[bool]>) {
+    assert_eq!(
+        data.len(),
+        width * height * 4,
+        "image data does not match its size"
+    );
+    if let Some(mask) = mask {
+        assert_eq!(
+            mask.len(),
+            width * height,
+            "mask does not match the image size"
+        );
+    }
+}
+
+// Whether each pixel of the image is to be sorted by the stage
+fn stage_matched<T: Channel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    stage: &Stage,
+    mask: Option<&[bool]>,
+) -> Vec<bool> {
+    let matched = stage.threshold.match_image(data, width, height);
+    reverse_and_mask(matched, stage, mask)
+}
+
+// Invert the pixels matched by the threshold of the stage if it is reversed, then keep only the
+// pixels the mask allows
+fn reverse_and_mask(mut matched: Vec<bool>, stage: &Stage, mask: Option<&[bool]>) -> Vec<bool> {
+    let reverse = stage.threshold_inverted();
+    match mask {
+        Some(mask) => matched
+            .par_iter_mut()
+            .zip(mask)
+            .for_each(|(matched, &allowed)| *matched = (*matched != reverse) && allowed),
+        None => matched
+            .par_iter_mut()
+            .for_each(|matched| *matched = *matched != reverse),
+    }
+    matched
+}
+
+// The paths the stage sorts along, or None for the rows of the image which don't need to be
+// gathered
+fn stage_paths<T: Channel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    stage: &Stage,
+) -> Option<Vec<paths::Path>> {
+    Some(match stage.direction {
+        SortDirection::Horizontal => return None,
+        SortDirection::Vertical => paths::line_paths(90., width, height),
+        SortDirection::Angle(angle) => paths::line_paths(angle, width, height),
+        SortDirection::Circles(center) => paths::circle_paths(center, width, height),
+        SortDirection::Spiral(center, spacing) => {
+            paths::spiral_paths(center, spacing, width, height)
+        }
+        SortDirection::Radial(center) => paths::radial_paths(center, width, height),
+        SortDirection::Edges => {
+            flow::flow_paths(&flow::edge_angles(data, width, height), width, height)
+        }
+        SortDirection::Noise(seed, scale, strength) => flow::flow_paths(
+            &flow::noise_angles(seed, scale, strength, width, height),
+            width,
+            height,
+        ),
+    })
+}
+
+// Sort every row of a rgba image in place using the stage settings, `matched` holds whether each
+// pixel is to be sorted and `mask` whether it may be.
+fn sort_rows<T: Channel>(
+    data: &mut [T],
+    matched: &[bool],
+    mask: Option<&[bool]>,
+    width: usize,
+    stage: &Stage,
+    control: &SortControl,
+) {
+    // Paralell loop over the rows of pixels
+    data.par_chunks_exact_mut(width * 4)
+        .zip(matched.par_chunks_exact(width))
+        .enumerate()
+        .for_each_init(RowScratch::default, |scratch, (y, (row, matched))| {
+            if !control.is_cancelled() {
+                let allowed = mask.map(|mask| &mask[y * width..(y + 1) * width]);
+                sort_row(
+                    row.as_chunks_mut::<4>().0,
+                    matched,
+                    allowed,
+                    y,
+                    stage,
+                    scratch,
+                );
+                control.finish_row();
+            }
+        });
+}
+
+// Sort the pixels of the source along each path using the stage settings, `matched` says whether
+// a pixel is to be sorted and `mask` whether it may be. Each path comes with its number, and they
+// must not overlap. Returns the sorted pixels of the paths one after the other.
+fn sort_paths<T: Channel>(
+    source: &[T],
+    matched: impl Fn(usize) -> bool + Sync,
+    mask: Option<&[bool]>,
+    paths: &[(usize, &paths::Path)],
+    stage: &Stage,
+    control: &SortControl,
+) -> Vec<[T; 4]> {
+    let pixels = source.as_chunks::<4>().0;
+    // Gather the pixels of all paths in paralell
+    let mut sorted: Vec<[T; 4]> = paths
+        .par_iter()
+        .flat_map_iter(|(_, path)| path.iter().map(|&i| pixels[i]))
+        .collect();
+    // and sort the part of each path, cancelled paths are left as they were
+    let mut rest = &mut sorted[..];
+    let mut rows = Vec::with_capacity(paths.len());
+    for &(index, path) in paths {
+        let (row, tail) = std::mem::take(&mut rest).split_at_mut(path.len());
+        rows.push((index, path, row));
+        rest = tail;
+    }
+    rows.into_par_iter().for_each_init(
+        || (RowScratch::default(), Vec::new(), Vec::new()),
+        |(scratch, row_matched, row_allowed), (index, path, row)| {
+            if !control.is_cancelled() {
+                row_matched.clear();
+                row_matched.extend(path.iter().map(|&i| matched(i)));
+                let allowed = mask.map(|mask| {
+                    row_allowed.clear();
+                    row_allowed.extend(path.iter().map(|&i| mask[i]));
+                    &row_allowed[..]
+                });
+                sort_row(row, row_matched, allowed, index, stage, scratch);
+                control.finish_row();
+            }
+        },
+    );
+    sorted
+}
+
+// Write the pixels sorted along the paths back to where they came from
+fn write_paths<T: Copy>(data: &mut [T], paths: &[(usize, &paths::Path)], sorted: &[[T; 4]]) {
+    let pixels = data.as_chunks_mut::<4>().0;
+    for (&i, pixel) in paths.iter().flat_map(|(_, path)| path.iter()).zip(sorted) {
+        pixels[i] = *pixel;
+    }
+}
+
+// Buffers reused between the rows and paths one thread sorts, so sorting them doesn't allocate.
+struct RowScratch<T> {
+    row_op: RowOp,
+    order: OrderScratch<T>,
+}
+
+impl<T> Default for RowScratch<T> {
+    fn default() -> Self {
+        Self {
+            row_op: RowOp::default(),
+            order: OrderScratch::default(),
+        }
+    }
+}
+
+// Sort a single row of rgba pixels in place using the stage settings, `index` is the number of
+// the row or path. `allowed` holds whether each pixel may be sorted, if there is a mask.
+fn sort_row<T: Channel>(
+    row: &mut [[T; 4]],
+    matched: &[bool],
+    allowed: Option<&[bool]>,
+    index: usize,
+    stage: &Stage,
+    scratch: &mut RowScratch<T>,
+) {
+    // Apply the threshold settings and the mask to this row
+    scratch.row_op.apply_threshold(matched, index, stage);
+    if let Some(allowed) = allowed {
+        scratch.row_op.apply_mask(allowed);
+    }
+
+    // loop over all parts of the row matched by the threshold and sort them
+    for &(start, end) in scratch.row_op.slices.iter() {
+        stage.ordering.sort(
+            &mut row[start..end],
+            stage.ordering_reverse,
+            &mut scratch.order,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    #[test]
+    fn scaled_pipeline() {
+        let pipeline = Pipeline {
+            stages: vec![
+                Stage {
+                    threshold: Threshold::Edges(0.7, 4.),
+                    extend_threshold_left: 10,
+                    extend_threshold_right: 3,
+                    merge_limit: 7,
+                    direction: SortDirection::Spiral([0.2, 0.4], 16.),
+                    ..Stage::default()
+                },
+                Stage {
+                    threshold: Threshold::Random(9, 3),
+                    direction: SortDirection::Noise(5, 100., 1.5),
+                    ..Stage::default()
+                },
+                Stage {
+                    threshold: Threshold::Fixed(1),
+                    ordering: PixelOrdering::Hue,
+                    direction: SortDirection::Circles([0.5, 0.1]),
+                    muted: true,
+                    ..Stage::default()
+                },
+            ],
+        };
+        assert_eq!(pipeline.scaled(1.), pipeline);
+
+        let mut expected = pipeline.clone();
+        let [first, second, third] = &mut expected.stages[..] else {
+            unreachable!()
+        };
+        first.threshold = Threshold::Edges(0.7, 2.);
+        first.extend_threshold_left = 5;
+        first.extend_threshold_right = 2;
+        first.merge_limit = 4;
+        first.direction = SortDirection::Spiral([0.2, 0.4], 8.);
+        second.threshold = Threshold::Random(5, 3);
+        second.direction = SortDirection::Noise(5, 50., 1.5);
+        // Intervals are at least a pixel long, centres and angles are relative to the size
+        third.threshold = Threshold::Fixed(1);
+        assert_eq!(pipeline.scaled(0.5), expected);
+    }
+
+    // Pixels the mask doesn't allow are neither matched nor moved, in any direction and with
+    // thresholds which match every pixel
+    #[test]
+    fn masked_pixels_stay_unsorted() {
+        let (width, height) = (29, 23);
+        let mut rng = Rng::new(11, 0);
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|_| rng.next_u64() as u8)
+            .collect();
+        // Blocks a rectangle in the middle and every fifth pixel
+        let mask: Vec<bool> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                !((8..20).contains(&x) && (5..15).contains(&y)) && i % 5 != 0
+            })
+            .collect();
+        for (threshold, direction) in [
+            (Threshold::Luminance(200.), SortDirection::Horizontal),
+            (Threshold::Random(9, 1), SortDirection::Vertical),
+            (Threshold::Fixed(6), SortDirection::Circles([0.5, 0.5])),
+            (Threshold::Edges(0.5, 1.), SortDirection::Edges),
+        ] {
+            let stage = Stage {
+                threshold,
+                direction,
+                extend_threshold_left: 2,
+                merge_limit: 3,
+                ..Stage::default()
+            };
+            let matched = stage_matched(&data, width, height, &stage, Some(&mask));
+            assert!(matched
+                .iter()
+                .zip(&mask)
+                .all(|(&m, &allowed)| allowed || !m));
+
+            let mut sorted = data.clone();
+            let pipeline = Pipeline {
+                stages: vec![stage],
+            };
+            sort_image_masked(&mut sorted, width, height, &pipeline, Some(&mask));
+            assert!(sorted != data, "{:?} sorted nothing", pipeline);
+            for (i, _) in mask.iter().enumerate().filter(|(_, &allowed)| !allowed) {
+                assert_eq!(
+                    sorted[i * 4..i * 4 + 4],
+                    data[i * 4..i * 4 + 4],
+                    "{:?}",
+                    pipeline
+                );
+            }
+        }
+    }
+
+    // Format a direction the way it is given on the command line
+    fn to_arg(direction: &SortDirection) -> String {
+        match direction {
+            SortDirection::Horizontal => "horizontal".to_string(),
+            SortDirection::Vertical => "vertical".to_string(),
+            SortDirection::Angle(angle) => format!("angle:{}", angle),
+            SortDirection::Circles([x, y]) => format!("circles:{}:{}", x, y),
+            SortDirection::Spiral([x, y], spacing) => format!("spiral:{}:{}:{}", x, y, spacing),
+            SortDirection::Radial([x, y]) => format!("radial:{}:{}", x, y),
+            SortDirection::Edges => "edges".to_string(),
+            SortDirection::Noise(seed, scale, strength) => {
+                format!("noise:{}:{}:{}", seed, scale, strength)
+            }
+        }
+    }
+
+    #[test]
+    fn parse_directions_round_trip() {
+        let directions = [
+            SortDirection::Horizontal,
+            SortDirection::Vertical,
+            SortDirection::Angle(45.),
+            SortDirection::Angle(-12.5),
+            SortDirection::Circles([0.5, 0.5]),
+            SortDirection::Spiral([0.25, 1.], 8.),
+            SortDirection::Radial([0., 0.75]),
+            SortDirection::Edges,
+            SortDirection::Noise(7, 120., 0.5),
+        ];
+        for direction in directions {
+            assert_eq!(to_arg(&direction).parse(), Ok(direction));
+        }
+    }
+
+    #[test]
+    fn parse_direction_errors() {
+        let errors = [
+            // Unknown modes
+            ("diagonal", "Unknown direction"),
+            ("", "Unknown direction"),
+            ("Vertical", "Unknown direction"),
+            // Missing and extra fields
+            ("angle", "Unknown direction"),
+            ("circles:0.5", "Unknown direction"),
+            ("spiral:0.5:0.5", "Unknown direction"),
+            ("noise:7:120", "Unknown direction"),
+            ("horizontal:1", "Unknown direction"),
+            // Values which are not numbers
+            ("angle:steep", "Invalid direction value \"steep\""),
+            ("radial:0.5:middle", "Invalid direction value \"middle\""),
+            ("spiral:0.5:0.5:", "Invalid direction value \"\""),
+            ("noise:-1:120:0.5", "Invalid noise seed \"-1\""),
+            ("noise:7:large:0.5", "Invalid direction value \"large\""),
+        ];
+        for (s, error) in errors {
+            match s.parse::<SortDirection>() {
+                Ok(direction) => panic!("{:?} parsed as {:?}", s, direction),
+                Err(e) => assert!(e.starts_with(error), "{:?}: {}", s, e),
+            }
+        }
+    }
+}
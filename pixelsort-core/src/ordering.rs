@@ -0,0 +1,382 @@
+use std::str::FromStr;
+
+use crate::{
+    color::{parse_color, pixel_to_hsv, pixel_to_luminance, DistanceMetric},
+    Channel,
+};
+
+/// Decides the order the pixels are sorted into.
+#[derive(Default, strum_macros::Display, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelOrdering {
+    /// Dark to light.
+    #[default]
+    Luminance,
+    /// Most to least similar to the colour, measured with the metric.
+    ColorSimilarity([u8; 3], DistanceMetric),
+    /// Around the colour wheel, from red over green and blue back to red.
+    Hue,
+    /// Grey to saturated, using the HSV saturation.
+    Saturation,
+    /// Dark to bright, using the HSV value (the brightest channel).
+    Value,
+    /// Dark to light, using the HSL lightness.
+    Lightness,
+    /// By the red channel, low to high.
+    Red,
+    /// By the green channel, low to high.
+    Green,
+    /// By the blue channel, low to high.
+    Blue,
+    /// Transparent to opaque.
+    Alpha,
+}
+
+// Implement the orderings
+impl PixelOrdering {
+    /// The value the ordering sorts a pixel by, ascending.
+    pub fn key<T: Channel>(&self, pixel: &[T; 4]) -> f32 {
+        match self {
+            PixelOrdering::Luminance => pixel_to_luminance(pixel),
+            PixelOrdering::ColorSimilarity(color, metric) => metric.distance(pixel, color),
+            PixelOrdering::Hue => pixel_to_hsv(pixel)[0],
+            PixelOrdering::Saturation => pixel_to_hsv(pixel)[1],
+            PixelOrdering::Value => pixel_to_hsv(pixel)[2],
+            PixelOrdering::Lightness => {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(Channel::to_f32);
+                (r.max(g).max(b) + r.min(g).min(b)) / 2.
+            }
+            PixelOrdering::Red => pixel[0].to_f32(),
+            PixelOrdering::Green => pixel[1].to_f32(),
+            PixelOrdering::Blue => pixel[2].to_f32(),
+            PixelOrdering::Alpha => pixel[3].to_f32(),
+        }
+    }
+
+    // Integer key of an 8-bit pixel in the same order as its key, for the orderings which only
+    // take the number of different values returned by byte_key_count
+    fn byte_key(&self, pixel: &[u8; 4]) -> usize {
+        let [r, g, b, a] = pixel.map(usize::from);
+        match self {
+            PixelOrdering::Luminance => r * 2 + g * 3 + b,
+            PixelOrdering::Value => r.max(g).max(b),
+            PixelOrdering::Lightness => r.max(g).max(b) + r.min(g).min(b),
+            PixelOrdering::Red => r,
+            PixelOrdering::Green => g,
+            PixelOrdering::Blue => b,
+            PixelOrdering::Alpha => a,
+            _ => unreachable!("no byte key"),
+        }
+    }
+
+    // Number of different byte keys of the ordering, if it has them
+    fn byte_key_count(&self) -> Option<usize> {
+        match self {
+            PixelOrdering::Luminance => Some(255 * 6 + 1),
+            PixelOrdering::Lightness => Some(255 * 2 + 1),
+            PixelOrdering::Value
+            | PixelOrdering::Red
+            | PixelOrdering::Green
+            | PixelOrdering::Blue
+            | PixelOrdering::Alpha => Some(256),
+            PixelOrdering::ColorSimilarity(..) | PixelOrdering::Hue | PixelOrdering::Saturation => {
+                None
+            }
+        }
+    }
+
+    /// Sort the pixels, returning their channels in the new order. Pixels which sort the same
+    /// keep their order, which is reversed along with the rest.
+    pub fn order<T: Channel>(&self, pixels: &[[T; 4]], reverse: bool) -> Vec<T> {
+        let mut sorted = pixels.to_vec();
+        self.sort(&mut sorted, reverse, &mut OrderScratch::default());
+        sorted.into_flattened()
+    }
+
+    // Sort the pixels in place like order, using the buffers of the scratch. The key of each
+    // pixel is computed once, and rows of 8-bit pixels long enough to be worth it are sorted by
+    // counting their keys.
+    pub(crate) fn sort<T: Channel>(
+        &self,
+        pixels: &mut [[T; 4]],
+        reverse: bool,
+        scratch: &mut OrderScratch<T>,
+    ) {
+        if pixels.len() < 2 {
+            return;
+        }
+        scratch.pixels.clear();
+        scratch.pixels.extend_from_slice(pixels);
+        let counting = self
+            .byte_key_count()
+            .filter(|&count| pixels.len() * 8 >= count && pixels[0][0].to_u8().is_some());
+        match counting {
+            Some(count) => {
+                let byte_key =
+                    |pixel: &[T; 4]| self.byte_key(&pixel.map(|c| c.to_u8().unwrap_or_default()));
+                // Where the pixels with each key start
+                let starts = &mut scratch.counts;
+                starts.clear();
+                starts.resize(count + 1, 0);
+                for pixel in pixels.iter() {
+                    starts[byte_key(pixel) + 1] += 1;
+                }
+                for key in 1..=count {
+                    starts[key] += starts[key - 1];
+                }
+                for pixel in &scratch.pixels {
+                    let start = &mut starts[byte_key(pixel)];
+                    pixels[*start] = *pixel;
+                    *start += 1;
+                }
+            }
+            None => {
+                // Ties are broken by position, the same order counting leaves them in
+                let keys = &mut scratch.keys;
+                keys.clear();
+                keys.extend(pixels.iter().enumerate().map(|(i, p)| (self.key(p), i)));
+                keys.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                for (pixel, &(_, i)) in pixels.iter_mut().zip(keys.iter()) {
+                    *pixel = scratch.pixels[i];
+                }
+            }
+        }
+        if reverse {
+            pixels.reverse();
+        }
+    }
+}
+
+// Buffers for sorting pixels, reused between the rows one thread sorts.
+pub(crate) struct OrderScratch<T> {
+    // Key and position of each pixel
+    keys: Vec<(f32, usize)>,
+    // Number of pixels with each byte key
+    counts: Vec<usize>,
+    // Copy of the unsorted pixels
+    pixels: Vec<[T; 4]>,
+}
+
+impl<T> Default for OrderScratch<T> {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            counts: Vec::new(),
+            pixels: Vec::new(),
+        }
+    }
+}
+
+// Parse orderings from the command line, e.g. "luminance" or "color:00ff00"
+impl FromStr for PixelOrdering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            ["luminance"] => Ok(PixelOrdering::Luminance),
+            ["color", color] => Ok(PixelOrdering::ColorSimilarity(
+                parse_color(color)?,
+                DistanceMetric::default(),
+            )),
+            ["color", color, metric] => Ok(PixelOrdering::ColorSimilarity(
+                parse_color(color)?,
+                metric.parse()?,
+            )),
+            ["hue"] => Ok(PixelOrdering::Hue),
+            ["saturation"] => Ok(PixelOrdering::Saturation),
+            ["value"] => Ok(PixelOrdering::Value),
+            ["lightness"] => Ok(PixelOrdering::Lightness),
+            ["red"] => Ok(PixelOrdering::Red),
+            ["green"] => Ok(PixelOrdering::Green),
+            ["blue"] => Ok(PixelOrdering::Blue),
+            ["alpha"] => Ok(PixelOrdering::Alpha),
+            _ => Err(format!(
+                "Unknown ordering \"{}\", expected \"luminance\", \"color:<hex color>[:<metric>]\", \"hue\", \"saturation\", \"value\", \"lightness\", \"red\", \"green\", \"blue\" or \"alpha\".",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    const COUNTED: [PixelOrdering; 7] = [
+        PixelOrdering::Luminance,
+        PixelOrdering::Value,
+        PixelOrdering::Lightness,
+        PixelOrdering::Red,
+        PixelOrdering::Green,
+        PixelOrdering::Blue,
+        PixelOrdering::Alpha,
+    ];
+
+    // Pixels made of a few channel values, so many of them sort the same without being equal
+    fn pixels(len: usize, seed: u32) -> Vec<[u8; 4]> {
+        let mut rng = Rng::new(seed, 0);
+        let values = [0, 1, 2, 51, 128, 200, 254, 255];
+        (0..len)
+            .map(|_| [(); 4].map(|_| values[rng.next_u64() as usize % values.len()]))
+            .collect()
+    }
+
+    // Stable sort by the float key, the order every sort must give
+    fn sorted_by_key<T: Channel>(
+        ordering: &PixelOrdering,
+        pixels: &[[T; 4]],
+        reverse: bool,
+    ) -> Vec<[T; 4]> {
+        let mut sorted = pixels.to_vec();
+        sorted.sort_by(|a, b| ordering.key(a).total_cmp(&ordering.key(b)));
+        if reverse {
+            sorted.reverse();
+        }
+        sorted
+    }
+
+    #[test]
+    fn counting_sort_matches_key_sort() {
+        for ordering in COUNTED {
+            assert!(ordering.byte_key_count().is_some());
+            // Long enough to be counted
+            let pixels = pixels(2000, 1);
+            for reverse in [false, true] {
+                let mut counted = pixels.clone();
+                ordering.sort(&mut counted, reverse, &mut OrderScratch::default());
+                assert_eq!(
+                    counted,
+                    sorted_by_key(&ordering, &pixels, reverse),
+                    "{:?} reverse {}",
+                    ordering,
+                    reverse
+                );
+            }
+        }
+    }
+
+    // The byte keys are in the same order as the float keys
+    #[test]
+    fn byte_keys_follow_keys() {
+        for ordering in COUNTED {
+            let mut pixels = pixels(500, 2);
+            pixels.sort_by(|a, b| ordering.key(a).total_cmp(&ordering.key(b)));
+            for pair in pixels.windows(2) {
+                let keys = [ordering.key(&pair[0]), ordering.key(&pair[1])];
+                let byte_keys = [ordering.byte_key(&pair[0]), ordering.byte_key(&pair[1])];
+                assert_eq!(
+                    keys[0] == keys[1],
+                    byte_keys[0] == byte_keys[1],
+                    "{:?} {:?}",
+                    ordering,
+                    pair
+                );
+                assert!(byte_keys[0] <= byte_keys[1], "{:?} {:?}", ordering, pair);
+            }
+        }
+    }
+
+    #[test]
+    fn key_sort_is_stable() {
+        let orderings = COUNTED.into_iter().chain([
+            PixelOrdering::Hue,
+            PixelOrdering::Saturation,
+            PixelOrdering::ColorSimilarity([0, 255, 0], DistanceMetric::Redmean),
+        ]);
+        for ordering in orderings {
+            // 16-bit pixels and short rows are never counted
+            let wide: Vec<[u16; 4]> = pixels(2000, 3)
+                .iter()
+                .map(|pixel| pixel.map(|c| c as u16 * 257))
+                .collect();
+            let short = pixels(20, 4);
+            for reverse in [false, true] {
+                let mut sorted = wide.clone();
+                ordering.sort(&mut sorted, reverse, &mut OrderScratch::default());
+                assert_eq!(
+                    sorted,
+                    sorted_by_key(&ordering, &wide, reverse),
+                    "{:?}",
+                    ordering
+                );
+                let mut sorted = short.clone();
+                ordering.sort(&mut sorted, reverse, &mut OrderScratch::default());
+                assert_eq!(
+                    sorted,
+                    sorted_by_key(&ordering, &short, reverse),
+                    "{:?}",
+                    ordering
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn order_reverses_ties() {
+        let pixels: [[u8; 4]; 4] = [[10, 0, 0, 255], [20, 0, 0, 1], [10, 0, 0, 7], [5, 9, 9, 9]];
+        assert_eq!(
+            PixelOrdering::Red.order(&pixels, false),
+            [[5, 9, 9, 9], [10, 0, 0, 255], [10, 0, 0, 7], [20, 0, 0, 1]].concat()
+        );
+        assert_eq!(
+            PixelOrdering::Red.order(&pixels, true),
+            [[20, 0, 0, 1], [10, 0, 0, 7], [10, 0, 0, 255], [5, 9, 9, 9]].concat()
+        );
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let orderings = [
+            ("luminance", PixelOrdering::Luminance),
+            (
+                "color:00ff80",
+                PixelOrdering::ColorSimilarity([0, 255, 128], DistanceMetric::default()),
+            ),
+            (
+                "color:#123456:oklab",
+                PixelOrdering::ColorSimilarity([18, 52, 86], DistanceMetric::Oklab),
+            ),
+            ("hue", PixelOrdering::Hue),
+            ("saturation", PixelOrdering::Saturation),
+            ("value", PixelOrdering::Value),
+            ("lightness", PixelOrdering::Lightness),
+            ("red", PixelOrdering::Red),
+            ("green", PixelOrdering::Green),
+            ("blue", PixelOrdering::Blue),
+            ("alpha", PixelOrdering::Alpha),
+        ];
+        for (s, ordering) in orderings {
+            assert_eq!(s.parse(), Ok(ordering.clone()));
+            // Every ordering without fields is named like its variant
+            if !matches!(ordering, PixelOrdering::ColorSimilarity(..)) {
+                assert_eq!(ordering.to_string().to_lowercase(), s);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let errors = [
+            // Unknown modes
+            ("brightness", "Unknown ordering"),
+            ("", "Unknown ordering"),
+            ("Hue", "Unknown ordering"),
+            // Missing and extra fields
+            ("color", "Unknown ordering"),
+            ("hue:1", "Unknown ordering"),
+            ("color:00ff00:oklab:1", "Unknown ordering"),
+            // Bad colours and metrics
+            ("color:green", "Invalid colour"),
+            ("color:00ff0", "Invalid colour"),
+            ("color:00ff00:manhattan", "Unknown distance metric"),
+        ];
+        for (s, error) in errors {
+            match s.parse::<PixelOrdering>() {
+                Ok(ordering) => panic!("{:?} parsed as {:?}", s, ordering),
+                Err(e) => assert!(e.starts_with(error), "{:?}: {}", s, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::f32::consts::TAU;
+
+// Paths are lists of pixel indices, in the order their pixels are sorted along.
+pub(crate) type Path = Vec<usize>;
+
+// Split the image into paths, `place` decides which of the paths a pixel belongs to and its
+// position along it. Every pixel belongs to exactly one path.
+fn bucket_paths(
+    width: usize,
+    height: usize,
+    paths: usize,
+    place: impl Fn(usize, usize) -> (usize, f32),
+) -> Vec<Path> {
+    let mut buckets: Vec<Vec<(f32, usize)>> = vec![vec![]; paths];
+    for y in 0..height {
+        for x in 0..width {
+            let (path, along) = place(x, y);
+            buckets[path].push((along, x + y * width));
+        }
+    }
+    buckets
+        .into_iter()
+        .filter(|bucket| !bucket.is_empty())
+        .map(|mut bucket| {
+            bucket.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            bucket.into_iter().map(|(_, index)| index).collect()
+        })
+        .collect()
+}
+
+// Split the image into parallel rasterised lines at the angle (degrees, clockwise from left to
+// right).
+pub(crate) fn line_paths(angle: f32, width: usize, height: usize) -> Vec<Path> {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Distance of a pixel from the line through the origin, and its position along that line.
+    let across = |x: usize, y: usize| (-(x as f32) * sin + y as f32 * cos).round() as isize;
+    let along = |x: usize, y: usize| x as f32 * cos + y as f32 * sin;
+
+    let corners = [
+        (0, 0),
+        (width - 1, 0),
+        (0, height - 1),
+        (width - 1, height - 1),
+    ];
+    let min_line = corners
+        .iter()
+        .map(|&(x, y)| across(x, y))
+        .min()
+        .unwrap_or(0);
+    let max_line = corners
+        .iter()
+        .map(|&(x, y)| across(x, y))
+        .max()
+        .unwrap_or(0);
+
+    // Pixels whose distance rounds to the same value make up one line
+    bucket_paths(width, height, (max_line - min_line + 1) as usize, |x, y| {
+        ((across(x, y) - min_line) as usize, along(x, y))
+    })
+}
+
+// How far outside of the image a centre can be, relative to its size. Centres further away would
+// need a huge number of paths, and look the same as parallel lines anyway.
+const MAX_CENTER_OUTSIDE: f32 = 1.;
+
+// Polar coordinates of the pixel centres around a centre relative to the image size.
+struct Polar {
+    center: (f32, f32),
+    // Distance from the centre to the furthest corner
+    max_radius: f32,
+}
+
+impl Polar {
+    fn new(center: [f32; 2], width: usize, height: usize) -> Self {
+        let [x, y] = center.map(|c| {
+            if c.is_finite() {
+                c.clamp(-MAX_CENTER_OUTSIDE, 1. + MAX_CENTER_OUTSIDE)
+            } else {
+                0.5
+            }
+        });
+        let center = (x * width as f32, y * height as f32);
+        let max_radius = [(0., 0.), (width as f32, 0.), (0., height as f32)]
+            .into_iter()
+            .chain([(width as f32, height as f32)])
+            .map(|(x, y)| (x - center.0).hypot(y - center.1))
+            .fold(0., f32::max);
+        Self { center, max_radius }
+    }
+
+    // Radius and clockwise angle (0 to TAU, starting to the right) of a pixel.
+    fn of(&self, x: usize, y: usize) -> (f32, f32) {
+        let dx = x as f32 + 0.5 - self.center.0;
+        let dy = y as f32 + 0.5 - self.center.1;
+        (dx.hypot(dy), dy.atan2(dx).rem_euclid(TAU))
+    }
+}
+
+// Split the image into concentric rings around the centre, each sorted clockwise.
+pub(crate) fn circle_paths(center: [f32; 2], width: usize, height: usize) -> Vec<Path> {
+    let polar = Polar::new(center, width, height);
+    let rings = polar.max_radius.round() as usize + 1;
+    bucket_paths(width, height, rings, |x, y| {
+        let (radius, angle) = polar.of(x, y);
+        ((radius.round() as usize).min(rings - 1), angle)
+    })
+}
+
+// Split the image into spokes going outwards from the centre.
+pub(crate) fn radial_paths(center: [f32; 2], width: usize, height: usize) -> Vec<Path> {
+    let polar = Polar::new(center, width, height);
+    // Enough spokes for them to be a pixel apart at the furthest corner
+    let spokes = ((TAU * polar.max_radius).round() as usize).max(1);
+    bucket_paths(width, height, spokes, |x, y| {
+        let (radius, angle) = polar.of(x, y);
+        (
+            ((angle / TAU * spokes as f32) as usize).min(spokes - 1),
+            radius,
+        )
+    })
+}
+
+// Split the image into interleaved archimedean spirals going clockwise outwards from the centre.
+// Each turn of a spiral is `spacing` pixels further out, so there are `spacing` spirals. Turns
+// further apart than the furthest corner are all the same single turn.
+pub(crate) fn spiral_paths(
+    center: [f32; 2],
+    spacing: f32,
+    width: usize,
+    height: usize,
+) -> Vec<Path> {
+    let polar = Polar::new(center, width, height);
+    let spacing = spacing.min(polar.max_radius).max(1.);
+    let spirals = spacing.ceil() as usize;
+    bucket_paths(width, height, spirals, |x, y| {
+        let (radius, angle) = polar.of(x, y);
+        // Radius the spiral through this pixel would have at angle 0 of this turn
+        let start = radius - spacing * angle / TAU;
+        let spiral = (start.rem_euclid(spacing) as usize).min(spirals - 1);
+        let turn = (start / spacing).floor();
+        (spiral, turn * TAU + angle)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const SIZES: [(usize, usize); 4] = [(1, 1), (7, 1), (1, 9), (23, 17)];
+
+    // Every pixel is on exactly one path, which writing the sorted paths back relies on
+    pub(crate) fn assert_partition(paths: &[Path], width: usize, height: usize) {
+        let mut seen = vec![false; width * height];
+        for &i in paths.iter().flatten() {
+            assert!(!seen[i], "pixel {} is on more than one path", i);
+            seen[i] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen), "a pixel is on no path");
+        assert!(paths.iter().all(|path| !path.is_empty()));
+    }
+
+    #[test]
+    fn angle_zero_follows_rows() {
+        for (width, height) in SIZES {
+            let rows: Vec<Path> = (0..height)
+                .map(|y| (y * width..(y + 1) * width).collect())
+                .collect();
+            assert_eq!(line_paths(0., width, height), rows);
+        }
+    }
+
+    // The rows sorted along an angle of 0 are numbered like the rows of a horizontal sort, so
+    // random intervals come out the same too
+    #[test]
+    fn angle_zero_sorts_like_horizontal() {
+        use crate::{random::Rng, sort_image, Pipeline, SortDirection, Stage, Threshold};
+        let (width, height) = (23, 17);
+        let mut rng = Rng::new(2, 0);
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|_| rng.next_u64() as u8)
+            .collect();
+        for threshold in [Threshold::Luminance(120.), Threshold::Random(6, 2)] {
+            let sorted = |direction| {
+                let pipeline = Pipeline {
+                    stages: vec![Stage {
+                        threshold: threshold.clone(),
+                        direction,
+                        ..Stage::default()
+                    }],
+                };
+                let mut data = data.clone();
+                sort_image(&mut data, width, height, &pipeline);
+                data
+            };
+            assert_eq!(
+                sorted(SortDirection::Angle(0.)),
+                sorted(SortDirection::Horizontal)
+            );
+        }
+    }
+
+    #[test]
+    fn vertical_follows_columns() {
+        for (width, height) in SIZES {
+            let paths = line_paths(90., width, height);
+            assert_eq!(paths.len(), width);
+            assert_partition(&paths, width, height);
+            // Each from the top to the bottom of a column
+            for path in paths {
+                let column: Path = (0..height).map(|y| path[0] + y * width).collect();
+                assert_eq!(path, column);
+            }
+        }
+    }
+
+    #[test]
+    fn angled_lines_partition() {
+        for (width, height) in SIZES {
+            for angle in [30., 45., 137., -60., 400.] {
+                assert_partition(&line_paths(angle, width, height), width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn circles_partition() {
+        for (width, height) in SIZES {
+            for center in [[0.5, 0.5], [0., 0.], [1., 0.3], [-3., 8.], [f32::NAN, 0.5]] {
+                assert_partition(&circle_paths(center, width, height), width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn radial_partition() {
+        for (width, height) in SIZES {
+            for center in [[0.5, 0.5], [0., 1.], [1000., 1000.], [f32::INFINITY, 0.]] {
+                assert_partition(&radial_paths(center, width, height), width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_partition() {
+        for (width, height) in SIZES {
+            for spacing in [0., 1., 2.5, 8., 1e9, f32::NAN] {
+                let paths = spiral_paths([0.3, 0.6], spacing, width, height);
+                assert_partition(&paths, width, height);
+            }
+        }
+    }
+
+    // Centres far outside of the image and huge spacings don't need more paths than pixels
+    #[test]
+    fn path_counts_are_bounded() {
+        let (width, height) = (40, 30);
+        let pixels = width * height;
+        assert!(radial_paths([1000., 1000.], width, height).len() <= pixels);
+        assert!(circle_paths([-1e30, 1e30], width, height).len() <= pixels);
+        assert!(spiral_paths([0.5, 0.5], 1e9, width, height).len() <= pixels);
+        let polar = Polar::new([1000., -1000.], width, height);
+        assert!(polar.max_radius < 4. * 50.);
+    }
+
+    #[test]
+    fn circles_are_sorted_clockwise() {
+        // The ring around the middle pixel of a 3x3 image starts to its right
+        let paths = circle_paths([0.5, 0.5], 3, 3);
+        assert_eq!(paths[0], [4]);
+        assert_eq!(paths[1], [5, 8, 7, 6, 3, 0, 1, 2]);
+    }
+}
@@ -0,0 +1,24 @@
+// Small seeded random number generator (SplitMix64), so results are reproducible everywhere.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    // Generator for one of many independent streams from the same seed, e.g. one per row.
+    pub(crate) fn new(seed: u32, stream: usize) -> Self {
+        let mut rng = Self(((seed as u64) << 32) ^ stream as u64);
+        rng.next_u64();
+        rng
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform float from 0 (inclusive) to 1 (exclusive).
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
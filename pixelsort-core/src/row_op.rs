@@ -0,0 +1,280 @@
+use crate::{random::Rng, Stage, Threshold};
+
+/// The ranges of a row which will be sorted.
+///
+/// Reusing it for the next row reuses its allocations.
+#[derive(Default, Debug)]
+pub struct RowOp {
+    /// Start (inclusive) and end (exclusive) pixel index of each range.
+    pub slices: Vec<(usize, usize)>,
+    // Buffers for splitting the slices: the positions to cut at, and the split slices
+    cuts: Vec<usize>,
+    split: Vec<(usize, usize)>,
+}
+
+impl RowOp {
+    // Adds a slice
+    fn add_slice(&mut self, (start, end): (usize, usize)) {
+        self.slices.push((start, end));
+    }
+
+    // Merge slices if their distance is less than the stage merge limit
+    fn merge_slice(&mut self, stage: &Stage) {
+        // Number of slices kept at the start of the list, the rest are merged into them
+        let mut kept = 0;
+        for i in 0..self.slices.len() {
+            let (start, end) = self.slices[i];
+            if kept > 0 {
+                let prev = &mut self.slices[kept - 1];
+                // Extending can make slices overlap, which always merges them
+                if prev.1 >= start || start - prev.1 <= stage.merge_limit {
+                    prev.1 = end;
+                    continue;
+                }
+            }
+            self.slices[kept] = (start, end);
+            kept += 1;
+        }
+        self.slices.truncate(kept);
+    }
+
+    // Extend slices by the stage values
+    fn extend_slices(&mut self, stage: &Stage, row_length: usize) {
+        // Start of the previous slice before it was extended
+        let mut prev_start = None;
+        for i in 0..self.slices.len() {
+            let slice = self.slices[i];
+            let end = match self.slices.get(i + 1) {
+                Some(next) => (slice.1 + stage.extend_threshold_right).min(next.1),
+                None => (slice.1 + stage.extend_threshold_right).min(row_length),
+            };
+            let start = match prev_start {
+                Some(prev_start) => {
+                    (slice.0.saturating_sub(stage.extend_threshold_left)).max(prev_start)
+                }
+                None => slice.0.saturating_sub(stage.extend_threshold_left),
+            };
+            prev_start = Some(slice.0);
+            self.slices[i] = (start, end);
+        }
+    }
+
+    // Split slices into intervals for the thresholds which set their length, `row` picks the
+    // random lengths of this row
+    fn split_slices(&mut self, stage: &Stage, row: usize, row_length: usize) {
+        // Ends of the intervals along the row
+        self.cuts.clear();
+        match stage.threshold {
+            Threshold::Random(length, seed) => {
+                let mut rng = Rng::new(seed, row);
+                let mut end = 0;
+                self.cuts.extend(
+                    std::iter::from_fn(|| {
+                        // Exponentially distributed lengths with the mean length
+                        let random = -(1. - rng.next_f32()).ln() * length.max(1) as f32;
+                        end += (random.round() as usize).max(1);
+                        Some(end)
+                    })
+                    .take_while(|&end| end < row_length),
+                );
+            }
+            Threshold::Fixed(length) => self
+                .cuts
+                .extend((length.max(1)..row_length).step_by(length.max(1))),
+            _ => return,
+        };
+        self.split.clear();
+        for &(start, end) in &self.slices {
+            // The cuts are in order, find the ones inside the slice
+            let first = self.cuts.partition_point(|&cut| cut <= start);
+            let mut from = start;
+            for &cut in self.cuts[first..].iter().take_while(|&&cut| cut < end) {
+                self.split.push((from, cut));
+                from = cut;
+            }
+            self.split.push((from, end));
+        }
+        std::mem::swap(&mut self.slices, &mut self.split);
+    }
+
+    /// Find the ranges of a row to sort, given whether each pixel of the row is to be sorted,
+    /// then extend, merge and split them according to the stage. `row` is the index of the row,
+    /// which seeds random intervals. The ranges of the previous row are replaced.
+    pub fn apply_threshold(&mut self, bools: &[bool], row: usize, stage: &Stage) {
+        self.slices.clear();
+        // Add a slice for every run of at least two matched pixels
+        let mut run_start = None;
+        for (i, &matched) in bools.iter().chain([&false]).enumerate() {
+            match (matched, run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    if i - start > 1 {
+                        self.add_slice((start, i));
+                    }
+                    run_start = None;
+                }
+                _ => (),
+            }
+        }
+        self.extend_slices(stage, bools.len());
+        self.merge_slice(stage);
+        self.split_slices(stage, row, bools.len());
+    }
+
+    /// Cut the ranges at the pixels which may not be sorted, given whether each pixel of the row
+    /// may be, so extending and merging them never reaches into masked pixels.
+    pub fn apply_mask(&mut self, allowed: &[bool]) {
+        self.split.clear();
+        for &(start, end) in &self.slices {
+            let mut run_start = None;
+            for (i, &allowed) in (start..).zip(allowed[start..end].iter().chain([&false])) {
+                match (allowed, run_start) {
+                    (true, None) => run_start = Some(i),
+                    (false, Some(run)) => {
+                        // Single pixels have nothing to be sorted with
+                        if i - run > 1 {
+                            self.split.push((run, i));
+                        }
+                        run_start = None;
+                    }
+                    _ => (),
+                }
+            }
+        }
+        std::mem::swap(&mut self.slices, &mut self.split);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slices(bools: &str, stage: &Stage) -> Vec<(usize, usize)> {
+        let bools: Vec<bool> = bools.chars().map(|c| c == '#').collect();
+        let mut row_op = RowOp::default();
+        row_op.apply_threshold(&bools, 0, stage);
+        row_op.slices
+    }
+
+    // Slices of a fully matched row of the length
+    fn row_slices(length: usize, row: usize, threshold: Threshold) -> Vec<(usize, usize)> {
+        let stage = Stage {
+            threshold,
+            ..Stage::default()
+        };
+        let mut row_op = RowOp::default();
+        row_op.apply_threshold(&vec![true; length], row, &stage);
+        row_op.slices
+    }
+
+    #[test]
+    fn masked_pixels_cut_slices() {
+        let stage = Stage {
+            extend_threshold_right: 2,
+            merge_limit: 2,
+            ..Stage::default()
+        };
+        let bools: Vec<bool> = "###..###.....##".chars().map(|c| c == '#').collect();
+        let allowed: Vec<bool> = "#.####.####.#.#".chars().map(|c| c == '#').collect();
+        let mut row_op = RowOp::default();
+        row_op.apply_threshold(&bools, 0, &stage);
+        assert_eq!(row_op.slices, [(0, 10), (13, 15)]);
+        row_op.apply_mask(&allowed);
+        assert_eq!(row_op.slices, [(2, 6), (7, 10)]);
+    }
+
+    #[test]
+    fn runs_of_matched_pixels() {
+        let stage = Stage::default();
+        assert_eq!(slices("##..###.#..##", &stage), [(0, 2), (4, 7), (11, 13)]);
+    }
+
+    // Slices extended past the start of the next one used to underflow when merging
+    #[test]
+    fn overlapping_extended_slices_merge() {
+        let stage = Stage {
+            extend_threshold_right: 5,
+            ..Stage::default()
+        };
+        assert_eq!(slices("##..##..##..##..............", &stage), [(0, 19)]);
+    }
+
+    #[test]
+    fn merge_limit() {
+        let stage = Stage {
+            merge_limit: 2,
+            ..Stage::default()
+        };
+        assert_eq!(slices("##.##.##..##...##", &stage), [(0, 12), (15, 17)]);
+    }
+
+    // The first two ranges of a row merge like the others
+    #[test]
+    fn merge_first_slices() {
+        let stage = Stage {
+            merge_limit: 5,
+            ..Stage::default()
+        };
+        assert_eq!(slices("##.##", &stage), [(0, 5)]);
+        assert_eq!(slices("##.....##", &stage), [(0, 9)]);
+        assert_eq!(slices("##......##", &stage), [(0, 2), (8, 10)]);
+    }
+
+    #[test]
+    fn fixed_lengths_split_slices() {
+        let stage = Stage {
+            threshold: Threshold::Fixed(4),
+            ..Stage::default()
+        };
+        assert_eq!(slices("##########", &stage), [(0, 4), (4, 8), (8, 10)]);
+    }
+
+    // The cuts are counted from the start of the row, not of each slice
+    #[test]
+    fn fixed_lengths_from_row_start() {
+        let stage = Stage {
+            threshold: Threshold::Fixed(4),
+            ..Stage::default()
+        };
+        assert_eq!(
+            slices("..#######...######", &stage),
+            [(2, 4), (4, 8), (8, 9), (12, 16), (16, 18)]
+        );
+        let slices = row_slices(1000, 3, Threshold::Fixed(7));
+        assert_eq!(slices.len(), 143);
+        assert!(slices[..142].iter().all(|(start, end)| end - start == 7));
+        assert_eq!(slices[142], (994, 1000));
+    }
+
+    #[test]
+    fn random_lengths_repeat_per_seed_and_row() {
+        let random = |row, seed| row_slices(500, row, Threshold::Random(10, seed));
+        assert_eq!(random(4, 1), random(4, 1));
+        assert_ne!(random(4, 1), random(4, 2));
+        assert_ne!(random(4, 1), random(5, 1));
+    }
+
+    #[test]
+    fn random_lengths_cover_row() {
+        for length in [0, 1, 3, 50, 2000] {
+            let slices = row_slices(1000, 0, Threshold::Random(length, 9));
+            assert_eq!(slices[0].0, 0);
+            assert_eq!(slices.last().unwrap().1, 1000);
+            assert!(slices.windows(2).all(|pair| pair[0].1 == pair[1].0));
+            assert!(slices.iter().all(|(start, end)| end > start));
+        }
+    }
+
+    #[test]
+    fn random_lengths_have_the_mean_length() {
+        for length in [5, 20, 80] {
+            let slices: Vec<(usize, usize)> = (0..50)
+                .flat_map(|row| row_slices(20000, row, Threshold::Random(length, 7)))
+                .collect();
+            // The last interval of each row is cut short by its end
+            let mean = (50 * 20000) as f32 / slices.len() as f32;
+            let error = (mean - length as f32).abs() / length as f32;
+            assert!(error < 0.03, "mean {} for length {}", mean, length);
+        }
+    }
+}
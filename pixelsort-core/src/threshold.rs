@@ -0,0 +1,329 @@
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::{
+    color::{parse_color, pixel_to_hsv, pixel_to_luminance, DistanceMetric},
+    filter, Channel,
+};
+
+/// Decides which pixels of a row are sorted.
+#[derive(strum_macros::Display, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Threshold {
+    /// Pixels with a luminance (0-255) below the value.
+    Luminance(f32),
+    /// Pixels with a distance to the colour below the value, measured with the metric.
+    ColorSimilarity(f32, [u8; 3], DistanceMetric),
+    /// Pixels with a hue (0-360 degrees) from the start to the end of the range. The range wraps
+    /// around 360 if the start is larger than the end, so `Hue(300., 60.)` matches reds. Greys
+    /// have no hue and are never matched.
+    Hue(f32, f32),
+    /// Pixels with a HSV saturation (0-1) between the minimum and maximum.
+    Saturation(f32, f32),
+    /// Pixels with a HSV value (0-1) between the minimum and maximum.
+    Value(f32, f32),
+    /// Pixels which are not on an edge of the image, so the sorted ranges are broken at edges.
+    /// The edges are found in the luminance blurred by the radius in pixels, with a sensitivity
+    /// from 0 (no edges) to 1 (every change in luminance).
+    Edges(f32, f32),
+    /// Every pixel, in intervals of random lengths. The lengths follow an exponential
+    /// distribution with the mean length in pixels, and are the same for the same seed.
+    Random(usize, u32),
+    /// Every pixel, in intervals of the fixed length in pixels from the start of each row.
+    Fixed(usize),
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold::Luminance(150.)
+    }
+}
+
+impl Threshold {
+    /// Whether inverting the threshold changes which pixels it matches. Random and fixed
+    /// intervals match every pixel, inverting them would match none, so it is ignored.
+    pub fn is_invertible(&self) -> bool {
+        !matches!(self, Threshold::Random(..) | Threshold::Fixed(_))
+    }
+
+    // Whether each pixel of a RGBA image is matched by the threshold
+    pub(crate) fn match_image<T: Channel>(
+        &self,
+        data: &[T],
+        width: usize,
+        height: usize,
+    ) -> Vec<bool> {
+        match *self {
+            // Edges depend on the neighbouring pixels
+            Threshold::Edges(sensitivity, blur) => {
+                filter::edge_map(data, width, height, sensitivity, blur)
+                    .into_par_iter()
+                    .map(|edge| !edge)
+                    .collect()
+            }
+            // These only split the rows into intervals
+            Threshold::Random(..) | Threshold::Fixed(_) => vec![true; width * height],
+            _ => data
+                .as_chunks::<4>()
+                .0
+                .par_iter()
+                .map(|pixel| self.matches(pixel))
+                .collect(),
+        }
+    }
+
+    // Whether a pixel is matched by the threshold, on its own. Edges can only be matched on the
+    // whole image.
+    pub(crate) fn matches<T: Channel>(&self, pixel: &[T; 4]) -> bool {
+        match self {
+            Threshold::Luminance(value) => pixel_to_luminance(pixel) < *value,
+            Threshold::ColorSimilarity(value, color, metric) => {
+                metric.distance(pixel, color) < *value
+            }
+            Threshold::Hue(start, end) => {
+                let [hue, saturation, _] = pixel_to_hsv(pixel);
+                // Greys have no hue to match
+                if saturation == 0. {
+                    false
+                } else if start <= end {
+                    (*start..=*end).contains(&hue)
+                } else {
+                    hue >= *start || hue <= *end
+                }
+            }
+            Threshold::Saturation(min, max) => (*min..=*max).contains(&pixel_to_hsv(pixel)[1]),
+            Threshold::Value(min, max) => (*min..=*max).contains(&pixel_to_hsv(pixel)[2]),
+            Threshold::Random(..) | Threshold::Fixed(_) => true,
+            Threshold::Edges(..) => unreachable!("matched on the whole image"),
+        }
+    }
+}
+
+// Parse a threshold value
+fn parse_value<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid threshold value \"{}\".", value))
+}
+
+// Parse thresholds from the command line, e.g. "luminance:150" or "color:1000:00ff00"
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            ["luminance", value] => value
+                .parse()
+                .map(Threshold::Luminance)
+                .map_err(|_| format!("Invalid luminance threshold value \"{}\".", value)),
+            ["color", value, color] => Ok(Threshold::ColorSimilarity(
+                parse_value(value)?,
+                parse_color(color)?,
+                DistanceMetric::default(),
+            )),
+            ["color", value, color, metric] => Ok(Threshold::ColorSimilarity(
+                parse_value(value)?,
+                parse_color(color)?,
+                metric.parse()?,
+            )),
+            ["hue", start, end] => Ok(Threshold::Hue(parse_value(start)?, parse_value(end)?)),
+            ["saturation", min, max] => Ok(Threshold::Saturation(
+                parse_value(min)?,
+                parse_value(max)?,
+            )),
+            ["value", min, max] => Ok(Threshold::Value(parse_value(min)?, parse_value(max)?)),
+            ["edges", sensitivity, blur] => Ok(Threshold::Edges(
+                parse_value(sensitivity)?,
+                parse_value(blur)?,
+            )),
+            ["random", length, seed] => {
+                Ok(Threshold::Random(parse_value(length)?, parse_value(seed)?))
+            }
+            ["fixed", length] => Ok(Threshold::Fixed(parse_value(length)?)),
+            _ => Err(format!(
+                "Unknown threshold \"{}\", expected \"luminance:<value>\", \"color:<value>:<hex color>[:<metric>]\", \"hue:<start>:<end>\", \"saturation:<min>:<max>\", \"value:<min>:<max>\", \"edges:<sensitivity>:<blur>\", \"random:<length>:<seed>\" or \"fixed:<length>\".",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const ORANGE: [u8; 4] = [255, 128, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+
+    #[test]
+    fn hue_range() {
+        let threshold = Threshold::Hue(30., 200.);
+        assert!(threshold.matches(&ORANGE));
+        assert!(threshold.matches(&GREEN));
+        assert!(!threshold.matches(&RED));
+        assert!(!threshold.matches(&BLUE));
+        assert!(!threshold.matches(&MAGENTA));
+        // The ends are included
+        assert!(Threshold::Hue(0., 120.).matches(&RED));
+        assert!(Threshold::Hue(0., 120.).matches(&GREEN));
+    }
+
+    #[test]
+    fn wrapping_hue_range() {
+        let threshold = Threshold::Hue(300., 60.);
+        assert!(threshold.matches(&RED));
+        assert!(threshold.matches(&ORANGE));
+        assert!(threshold.matches(&MAGENTA));
+        assert!(threshold.matches(&[255u8, 0, 40, 255]));
+        assert!(!threshold.matches(&GREEN));
+        assert!(!threshold.matches(&BLUE));
+    }
+
+    #[test]
+    fn hue_of_greys() {
+        for threshold in [
+            Threshold::Hue(0., 360.),
+            Threshold::Hue(300., 60.),
+            Threshold::Hue(0., 10.),
+        ] {
+            for grey in [0u8, 1, 128, 255] {
+                assert!(!threshold.matches(&[grey, grey, grey, 255]), "{}", grey);
+            }
+            assert!(!threshold.matches(&[30000u16, 30000, 30000, 65535]));
+            // The slightest colour has a hue
+            assert!(threshold.matches(&[129u8, 128, 128, 255]));
+        }
+    }
+
+    // Inverted random and fixed intervals still sort every pixel, instead of none
+    #[test]
+    fn interval_thresholds_ignore_invert() {
+        use crate::{random::Rng, sort_image, Pipeline, Stage};
+        let (width, height) = (31, 7);
+        let mut rng = Rng::new(5, 0);
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|_| rng.next_u64() as u8)
+            .collect();
+        for threshold in [Threshold::Random(6, 3), Threshold::Fixed(5)] {
+            assert!(!threshold.is_invertible());
+            let sorted = |threshold_reverse| {
+                let pipeline = Pipeline {
+                    stages: vec![Stage {
+                        threshold: threshold.clone(),
+                        threshold_reverse,
+                        ..Stage::default()
+                    }],
+                };
+                let mut data = data.clone();
+                sort_image(&mut data, width, height, &pipeline);
+                data
+            };
+            assert_ne!(sorted(true), data);
+            assert_eq!(sorted(true), sorted(false));
+        }
+        assert!(Threshold::Luminance(100.).is_invertible());
+        assert!(Threshold::Edges(0.5, 1.).is_invertible());
+    }
+
+    // Format a threshold the way it is given on the command line
+    fn to_arg(threshold: &Threshold) -> String {
+        let hex = |[r, g, b]: [u8; 3]| format!("{:02x}{:02x}{:02x}", r, g, b);
+        match threshold {
+            Threshold::Luminance(value) => format!("luminance:{}", value),
+            Threshold::ColorSimilarity(value, color, metric) => format!(
+                "color:{}:{}:{}",
+                value,
+                hex(*color),
+                metric.to_string().to_lowercase()
+            ),
+            Threshold::Hue(start, end) => format!("hue:{}:{}", start, end),
+            Threshold::Saturation(min, max) => format!("saturation:{}:{}", min, max),
+            Threshold::Value(min, max) => format!("value:{}:{}", min, max),
+            Threshold::Edges(sensitivity, blur) => format!("edges:{}:{}", sensitivity, blur),
+            Threshold::Random(length, seed) => format!("random:{}:{}", length, seed),
+            Threshold::Fixed(length) => format!("fixed:{}", length),
+        }
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let thresholds = [
+            Threshold::Luminance(150.),
+            Threshold::Luminance(12.5),
+            Threshold::ColorSimilarity(1000., [0, 255, 0], DistanceMetric::Redmean),
+            Threshold::ColorSimilarity(0.25, [18, 52, 171], DistanceMetric::Oklab),
+            Threshold::ColorSimilarity(20., [255, 0, 128], DistanceMetric::Ciede2000),
+            Threshold::Hue(300., 60.),
+            Threshold::Saturation(0.2, 1.),
+            Threshold::Value(0., 0.5),
+            Threshold::Edges(0.5, 2.),
+            Threshold::Random(40, 7),
+            Threshold::Fixed(16),
+        ];
+        for threshold in thresholds {
+            assert_eq!(to_arg(&threshold).parse(), Ok(threshold));
+        }
+    }
+
+    #[test]
+    fn parse_defaults_and_hash() {
+        assert_eq!(
+            "color:100:#ff8000".parse(),
+            Ok(Threshold::ColorSimilarity(
+                100.,
+                [255, 128, 0],
+                DistanceMetric::default()
+            ))
+        );
+        assert_eq!(
+            "color:100:FF8000:cie76".parse(),
+            Ok(Threshold::ColorSimilarity(
+                100.,
+                [255, 128, 0],
+                DistanceMetric::Cie76
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let errors = [
+            // Unknown modes
+            ("brightness:150", "Unknown threshold"),
+            ("", "Unknown threshold"),
+            ("Luminance:150", "Unknown threshold"),
+            // Missing and extra fields
+            ("luminance", "Unknown threshold"),
+            ("hue:300", "Unknown threshold"),
+            ("color:100", "Unknown threshold"),
+            ("edges:0.5", "Unknown threshold"),
+            ("random:40", "Unknown threshold"),
+            ("fixed:16:2", "Unknown threshold"),
+            // Values which are not numbers
+            (
+                "luminance:bright",
+                "Invalid luminance threshold value \"bright\"",
+            ),
+            ("hue:red:60", "Invalid threshold value \"red\""),
+            ("saturation:0.2:", "Invalid threshold value \"\""),
+            ("random:-4:7", "Invalid threshold value \"-4\""),
+            ("random:40:1.5", "Invalid threshold value \"1.5\""),
+            ("fixed:ten", "Invalid threshold value \"ten\""),
+            // Bad colours and metrics
+            ("color:100:00ff0", "Invalid colour"),
+            ("color:100:00gg00", "Invalid colour"),
+            ("color:100:00ff00:manhattan", "Unknown distance metric"),
+        ];
+        for (s, error) in errors {
+            match s.parse::<Threshold>() {
+                Ok(threshold) => panic!("{:?} parsed as {:?}", s, threshold),
+                Err(e) => assert!(e.starts_with(error), "{:?}: {}", s, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+use bevy_egui::EguiContext;
+use bevy_pancam::PanCam;
+use pixelsort_core::{Pipeline, SortDirection};
+
+use crate::{mask::brush_held, Canvas, PixelsortImage};
+
+// Stage whose centre is set by the next click on the canvas, if any.
+#[derive(Default)]
+pub(crate) struct CenterPicker(pub(crate) Option<usize>);
+
+// Position of the cursor on the canvas, relative to the image size: (0, 0) is the top left
+// corner and (1, 1) the bottom right one.
+pub(crate) fn cursor_on_canvas(
+    windows: &Windows,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+    canvas_transform: &GlobalTransform,
+    image_size: Vec2,
+) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    let window_size = Vec2::new(window.width(), window.height());
+
+    // Cursor to normalized device coordinates, then into the world
+    let ndc = (cursor / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let world = ndc_to_world.project_point3(ndc.extend(-1.0));
+
+    // and into the space of the canvas sprite, which is centered on its transform
+    let local = canvas_transform
+        .compute_matrix()
+        .inverse()
+        .transform_point3(world);
+    Some(Vec2::new(
+        local.x / image_size.x + 0.5,
+        0.5 - local.y / image_size.y,
+    ))
+}
+
+// RGBA image of the size, for an overlay over the canvas.
+pub(crate) fn overlay_image(size: Extent3d, data: Vec<u8>) -> Image {
+    let mut image = Image::new(
+        size,
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+// Show an image over the canvas, moved by the translation in pixels. Higher depths are drawn on
+// top, and moving the canvas moves the image with it.
+pub(crate) fn spawn_overlay(
+    canvas: &Canvas,
+    image: Handle<Image>,
+    translation: Vec3,
+    commands: &mut Commands,
+) -> Option<Entity> {
+    let canvas = canvas.0?;
+    let entity = commands
+        .spawn_bundle(SpriteBundle {
+            texture: image,
+            transform: Transform::from_translation(translation),
+            ..default()
+        })
+        .id();
+    commands.entity(canvas).add_child(entity);
+    Some(entity)
+}
+
+// The centre of a sort direction, if it has one.
+pub(crate) fn center_mut(direction: &mut SortDirection) -> Option<&mut [f32; 2]> {
+    match direction {
+        SortDirection::Circles(center)
+        | SortDirection::Spiral(center, _)
+        | SortDirection::Radial(center) => Some(center),
+        _ => None,
+    }
+}
+
+// System which stops panning while clicks and drags are meant for the centre picker or the brush
+pub(crate) fn toggle_pancam(
+    picker: Res<CenterPicker>,
+    keys: Res<Input<KeyCode>>,
+    mut pancams: Query<&mut PanCam>,
+) {
+    for mut pancam in pancams.iter_mut() {
+        pancam.enabled = picker.0.is_none() && !brush_held(&keys);
+    }
+}
+
+// System which sets the centre of a stage to where the canvas was clicked
+pub(crate) fn pick_center(
+    mut picker: ResMut<CenterPicker>,
+    mut pipeline: ResMut<Pipeline>,
+    mouse: Res<Input<MouseButton>>,
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    canvas: Res<Canvas>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    images: Res<Assets<Image>>,
+) {
+    let Some(index) = picker.0 else {
+        return;
+    };
+    if !mouse.just_pressed(MouseButton::Left) || egui_context.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let image_size = pixelsimage
+        .as_ref()
+        .and_then(|pixelsimg| images.get(&pixelsimg.dest))
+        .map(|image| image.size());
+    let canvas_transform = canvas.0.and_then(|entity| transforms.get(entity).ok());
+    if let (Some(image_size), Some(canvas_transform), Ok(camera)) =
+        (image_size, canvas_transform, cameras.get_single())
+    {
+        let position = cursor_on_canvas(&windows, camera, canvas_transform, image_size);
+        let center = pipeline
+            .stages
+            .get_mut(index)
+            .and_then(|stage| center_mut(&mut stage.direction));
+        if let (Some(position), Some(center)) = (position, center) {
+            *center = position.clamp(Vec2::ZERO, Vec2::ONE).to_array();
+        }
+    }
+    picker.0 = None;
+}
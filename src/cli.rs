@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use pixelsort_core::{sort_image_masked, Pipeline, PixelOrdering, SortDirection, Stage, Threshold};
+
+use crate::{export::write_image, mask::load_mask, presets::load_preset, working::BitDepth};
+
+// Command line interface, without a subcommand the UI is opened.
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Pixelsort images, interactively or from the command line.",
+    args_conflicts_with_subcommands = true
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+    /// Image to open in the UI.
+    pub(crate) image: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Sort an image and write the result, without opening a window.
+    Sort(SortArgs),
+}
+
+// Options which make up the single stage used when no preset is given.
+const STAGE_ARGS: [&str; 8] = [
+    "threshold",
+    "invert",
+    "ordering",
+    "reverse",
+    "extend_left",
+    "extend_right",
+    "merge",
+    "direction",
+];
+
+#[derive(Args)]
+pub(crate) struct SortArgs {
+    /// Image to sort.
+    input: PathBuf,
+    /// Where to write the sorted image, the format is taken from the extension.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Mask image, only its white pixels are sorted. It is scaled to the size of the image.
+    #[arg(long)]
+    mask: Option<PathBuf>,
+    /// Preset file with the stages to apply, instead of the single stage options below.
+    #[arg(short, long, conflicts_with_all = STAGE_ARGS)]
+    preset: Option<PathBuf>,
+    /// Threshold: "luminance:<value>", "color:<value>:<hex color>[:<metric>]",
+    /// "hue:<start>:<end>", "saturation:<min>:<max>", "value:<min>:<max>",
+    /// "edges:<sensitivity>:<blur>", "random:<length>:<seed>" or "fixed:<length>".
+    /// Metrics are "euclidean", "redmean" (default), "cie76", "ciede2000" and "oklab".
+    #[arg(short, long, default_value = "luminance:150")]
+    threshold: Threshold,
+    /// Invert the threshold, matching the other direction. Ignored for random and fixed intervals.
+    #[arg(long)]
+    invert: bool,
+    /// Ordering: "luminance", "color:<hex color>[:<metric>]", "hue", "saturation", "value", "lightness",
+    /// "red", "green", "blue" or "alpha".
+    #[arg(long, default_value = "luminance")]
+    ordering: PixelOrdering,
+    /// Reverse the ordering of the sorted pixels.
+    #[arg(short, long)]
+    reverse: bool,
+    /// Extend the sorted ranges this many pixels to the left.
+    #[arg(long, default_value_t = 0)]
+    extend_left: usize,
+    /// Extend the sorted ranges this many pixels to the right.
+    #[arg(long, default_value_t = 0)]
+    extend_right: usize,
+    /// Merge sorted ranges which are at most this many pixels apart.
+    #[arg(short, long, default_value_t = 0)]
+    merge: usize,
+    /// Direction to sort in: "horizontal", "vertical", "angle:<degrees>" (clockwise from left to
+    /// right), "circles:<x>:<y>", "spiral:<x>:<y>:<spacing>", "radial:<x>:<y>", "edges" or
+    /// "noise:<seed>:<scale>:<strength>". Centres are relative to the image size, 0.5:0.5 being
+    /// the middle.
+    #[arg(short, long, default_value = "horizontal")]
+    direction: SortDirection,
+    /// Quality of the output when writing a JPEG.
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+}
+
+impl Command {
+    // Run the command, returning the exit code of the process.
+    pub(crate) fn run(self) -> i32 {
+        let result = match self {
+            Command::Sort(args) => sort(args),
+        };
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                1
+            }
+        }
+    }
+}
+
+impl From<&SortArgs> for Pipeline {
+    fn from(args: &SortArgs) -> Self {
+        Pipeline {
+            stages: vec![Stage {
+                threshold: args.threshold.clone(),
+                threshold_reverse: args.invert,
+                ordering: args.ordering.clone(),
+                ordering_reverse: args.reverse,
+                extend_threshold_left: args.extend_left,
+                extend_threshold_right: args.extend_right,
+                merge_limit: args.merge,
+                direction: args.direction,
+                muted: false,
+            }],
+        }
+    }
+}
+
+fn sort(args: SortArgs) -> Result<(), String> {
+    let pipeline = match args.preset {
+        Some(ref path) => load_preset(path)?,
+        None => Pipeline::from(&args),
+    };
+
+    // Sort at full precision, and write the result at the bit depth of the input
+    let img = image::open(&args.input)
+        .map_err(|e| format!("Failed to open {}: {}", args.input.display(), e))?;
+    let depth = BitDepth::of(img.color());
+    let mut img = img.into_rgba16();
+
+    let (width, height) = img.dimensions();
+    let mask = match args.mask {
+        Some(ref path) => Some(load_mask(path, width, height)?),
+        None => None,
+    };
+    sort_image_masked(
+        &mut img,
+        width as usize,
+        height as usize,
+        &pipeline,
+        mask.as_deref(),
+    );
+
+    write_image(
+        &img.into_raw(),
+        width,
+        height,
+        &args.output,
+        depth,
+        args.quality,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{error::ErrorKind, Parser};
+    use pixelsort_core::DistanceMetric;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<SortArgs, clap::Error> {
+        let args = ["pixelsort", "sort", "in.png", "-o", "out.png"]
+            .iter()
+            .chain(args);
+        Cli::try_parse_from(args).map(|cli| match cli.command {
+            Some(Command::Sort(args)) => args,
+            None => panic!("no sort command"),
+        })
+    }
+
+    #[test]
+    fn defaults_are_the_default_stage() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.input, PathBuf::from("in.png"));
+        assert_eq!(args.output, PathBuf::from("out.png"));
+        assert_eq!(args.quality, 90);
+        assert_eq!(Pipeline::from(&args).stages, vec![Stage::default()]);
+    }
+
+    #[test]
+    fn stage_options() {
+        let args = parse(&[
+            "-t",
+            "color:300:ff8000:oklab",
+            "--invert",
+            "--ordering",
+            "hue",
+            "-r",
+            "--extend-left",
+            "2",
+            "--extend-right",
+            "3",
+            "-m",
+            "4",
+            "-d",
+            "spiral:0.5:0.25:6",
+            "--quality",
+            "75",
+        ])
+        .unwrap();
+        assert_eq!(args.quality, 75);
+        assert_eq!(
+            Pipeline::from(&args).stages,
+            vec![Stage {
+                threshold: Threshold::ColorSimilarity(300., [255, 128, 0], DistanceMetric::Oklab),
+                threshold_reverse: true,
+                ordering: PixelOrdering::Hue,
+                ordering_reverse: true,
+                extend_threshold_left: 2,
+                extend_threshold_right: 3,
+                merge_limit: 4,
+                direction: SortDirection::Spiral([0.5, 0.25], 6.),
+                muted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_options() {
+        let errors = [
+            // Unknown modes
+            (&["-t", "brightness:150"][..], "Unknown threshold"),
+            (&["--ordering", "brightness"], "Unknown ordering"),
+            (&["-d", "diagonal"], "Unknown direction"),
+            // Missing fields
+            (&["-t", "hue:300"], "Unknown threshold"),
+            (&["--ordering", "color"], "Unknown ordering"),
+            (&["-d", "circles:0.5"], "Unknown direction"),
+            // Values which are not numbers
+            (
+                &["-t", "luminance:bright"],
+                "Invalid luminance threshold value",
+            ),
+            (&["-d", "angle:steep"], "Invalid direction value"),
+            (&["-m", "few"], "invalid digit"),
+            (&["--quality", "high"], "invalid digit"),
+        ];
+        for (args, error) in errors {
+            let e = parse(args).err().expect("invalid options fail");
+            assert_eq!(e.kind(), ErrorKind::ValueValidation, "{:?}", args);
+            assert!(e.to_string().contains(error), "{:?}: {}", args, e);
+        }
+        // Out of range and conflicting options
+        let e = parse(&["--quality", "0"]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        let e = parse(&["-p", "preset.ron", "-t", "fixed:8"]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        // The output is required
+        let e = Cli::try_parse_from(["pixelsort", "sort", "in.png"])
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn image_without_command() {
+        let cli = Cli::try_parse_from(["pixelsort", "in.png"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.image, Some(PathBuf::from("in.png")));
+    }
+}
@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+
+use crate::{
+    canvas::{overlay_image, spawn_overlay},
+    Canvas, PixelsortImage,
+};
+
+// Colour of the line between the original and sorted halves.
+const SPLIT_LINE_COLOR: [u8; 4] = [255, 255, 255, 255];
+// Space between the images shown side by side, relative to the width of the image.
+const SIDE_BY_SIDE_GAP: f32 = 0.02;
+
+// How the original image is shown next to the sorted one.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub(crate) enum CompareMode {
+    Off,
+    // The original left of the split, the sorted image right of it
+    Split,
+    // The original left of the sorted image
+    SideBySide,
+}
+
+// Comparison settings, and the entity showing the original.
+pub(crate) struct Compare {
+    pub(crate) mode: CompareMode,
+    // Position of the split, relative to the width of the image
+    pub(crate) split: f32,
+    overlay: Option<Handle<Image>>,
+    entity: Option<Entity>,
+}
+
+impl Default for Compare {
+    fn default() -> Self {
+        Self {
+            mode: CompareMode::Off,
+            split: 0.5,
+            overlay: None,
+            entity: None,
+        }
+    }
+}
+
+// Pixels of the original left of the split, and transparent ones right of it.
+fn split_overlay(source: &[u8], width: usize, split: f32) -> Vec<u8> {
+    let split = ((split.clamp(0., 1.) * width as f32).round() as usize).min(width);
+    source
+        .chunks_exact(width * 4)
+        .flat_map(|row| {
+            (0..width).flat_map(move |x| match x {
+                // A line marks the split
+                x if x + 1 == split || (split == 0 && x == 0) => SPLIT_LINE_COLOR,
+                x if x < split => [row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]],
+                _ => [0; 4],
+            })
+        })
+        .collect()
+}
+
+// System which shows the original image split with or next to the sorted one
+pub(crate) fn update_compare(
+    mut compare: ResMut<Compare>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    canvas: Res<Canvas>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut last: Local<Option<(CompareMode, f32)>>,
+    mut commands: Commands,
+) {
+    let Some(pixelsimg) = pixelsimage else {
+        return;
+    };
+    let source_changed = image_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == pixelsimg.source
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let state = (compare.mode, compare.split);
+    if *last == Some(state) && !source_changed && !pixelsimg.is_changed() {
+        return;
+    }
+    *last = Some(state);
+
+    if let Some(entity) = compare.entity.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+    compare.overlay = None;
+    let Some(source) = images.get(&pixelsimg.source) else {
+        return;
+    };
+    let size = source.texture_descriptor.size;
+    match compare.mode {
+        CompareMode::Off => (),
+        CompareMode::Split => {
+            let data = split_overlay(&source.data, size.width as usize, compare.split);
+            let overlay = images.add(overlay_image(size, data));
+            // Between the canvas and the other overlays
+            compare.entity = spawn_overlay(&canvas, overlay.clone(), Vec3::Z * 0.25, &mut commands);
+            compare.overlay = Some(overlay);
+        }
+        CompareMode::SideBySide => {
+            let offset = size.width as f32 * (1. + SIDE_BY_SIDE_GAP);
+            compare.entity = spawn_overlay(
+                &canvas,
+                pixelsimg.source.clone(),
+                Vec3::new(-offset, 0., 0.),
+                &mut commands,
+            );
+        }
+    }
+}
+
+// System which shows the original image instead of the sorted one while Space is held
+pub(crate) fn hold_original(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    canvas: Res<Canvas>,
+    mut showing_original: Local<bool>,
+    mut commands: Commands,
+) {
+    let (Some(pixelsimg), Some(entity)) = (pixelsimage, canvas.0) else {
+        return;
+    };
+    let hold = keys.pressed(KeyCode::Space) && !egui_context.ctx_mut().wants_keyboard_input();
+    // Swap the texture back and forth only when the key changes, or a new image was loaded
+    if hold == *showing_original && !pixelsimg.is_changed() {
+        return;
+    }
+    *showing_original = hold;
+    let texture = if hold {
+        &pixelsimg.source
+    } else {
+        &pixelsimg.dest
+    };
+    commands.entity(entity).insert(texture.as_weak::<Image>());
+}
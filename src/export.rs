@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use image::{codecs::jpeg::JpegEncoder, ImageBuffer, ImageFormat, Rgba, RgbaImage};
+
+use crate::{
+    working::{to_rgba8, BitDepth, WorkingImage},
+    PixelsortImage,
+};
+
+// Image formats which can be exported, depending on the enabled features.
+pub(crate) const EXPORT_FORMATS: &[(&str, &[&str])] = &[
+    ("PNG", &["png"]),
+    #[cfg(feature = "formats")]
+    ("JPEG", &["jpg", "jpeg"]),
+    #[cfg(feature = "formats")]
+    ("BMP", &["bmp"]),
+    #[cfg(feature = "formats")]
+    ("TGA", &["tga"]),
+];
+
+// Export settings set in the UI, and the result of the last export.
+pub(crate) struct ExportSettings {
+    pub(crate) jpeg_quality: u8,
+    pub(crate) status: Option<Result<PathBuf, String>>,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            jpeg_quality: 90,
+            status: None,
+        }
+    }
+}
+
+// Event dispatched when the sorted image should be written to a file.
+pub(crate) struct ExportEvent(pub(crate) PathBuf);
+
+// Write RGBA16 data to a file at the bit depth, the format is taken from the extension. Only PNG
+// can hold 16 bits, the other formats are always written with 8.
+pub(crate) fn write_image(
+    data: &[u16],
+    width: u32,
+    height: u32,
+    path: &Path,
+    depth: BitDepth,
+    jpeg_quality: u8,
+) -> Result<(), String> {
+    let format = ImageFormat::from_path(path).map_err(|e| e.to_string())?;
+    let supported = EXPORT_FORMATS.iter().any(|(_, extensions)| {
+        extensions
+            .iter()
+            .any(|ext| ImageFormat::from_extension(ext) == Some(format))
+    });
+    if !supported {
+        return Err(format!("Can't export {} files.", format_name(path)));
+    }
+
+    let size_error = || "Image data does not match its size.".to_owned();
+    if format == ImageFormat::Png && depth == BitDepth::Sixteen {
+        let img: ImageBuffer<Rgba<u16>, _> =
+            ImageBuffer::from_raw(width, height, data.to_vec()).ok_or_else(size_error)?;
+        return img
+            .save_with_format(path, format)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+    }
+    let img = RgbaImage::from_raw(width, height, to_rgba8(data)).ok_or_else(size_error)?;
+
+    let result = if format == ImageFormat::Jpeg {
+        // Jpeg has no alpha channel, and needs the quality passed to the encoder.
+        std::fs::File::create(path)
+            .map_err(image::ImageError::IoError)
+            .and_then(|mut file| {
+                JpegEncoder::new_with_quality(&mut file, jpeg_quality)
+                    .encode_image(&image::DynamicImage::ImageRgba8(img).into_rgb8())
+            })
+    } else {
+        img.save_with_format(path, format)
+    };
+    result.map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn format_name(path: &Path) -> String {
+    match path.extension() {
+        Some(ext) => ext.to_string_lossy().to_uppercase(),
+        None => "extensionless".to_owned(),
+    }
+}
+
+// System which writes the sorted image to disk
+pub(crate) fn export(
+    mut evt: EventReader<ExportEvent>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    images: Res<Assets<Image>>,
+    working: Res<WorkingImage>,
+    mut export_settings: ResMut<ExportSettings>,
+) {
+    for ExportEvent(path) in evt.iter() {
+        let result = match pixelsimage
+            .as_ref()
+            .and_then(|pixelsimg| images.get(&pixelsimg.dest))
+        {
+            Some(dest) => {
+                let size = dest.texture_descriptor.size;
+                write_image(
+                    &working.dest,
+                    size.width,
+                    size.height,
+                    path,
+                    working.depth,
+                    export_settings.jpeg_quality,
+                )
+            }
+            None => Err("No image loaded to export.".to_owned()),
+        };
+        export_settings.status = Some(result.map(|_| path.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write the data and read it back as RGBA16, along with the bit depth it was written at
+    fn round_trip(data: &[u16], extension: &str, depth: BitDepth) -> (Vec<u16>, BitDepth) {
+        let path = std::env::temp_dir().join(format!(
+            "pixelsort-export-test-{}-{:?}.{}",
+            std::process::id(),
+            depth,
+            extension
+        ));
+        write_image(data, 2, 1, &path, depth, 90).unwrap();
+        let read = image::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        (read.to_rgba16().into_raw(), BitDepth::of(read.color()))
+    }
+
+    #[test]
+    fn sixteen_bit_png() {
+        let data = [1, 300, 40000, 65535, 12345, 0, 54321, 1000];
+        assert_eq!(
+            round_trip(&data, "png", BitDepth::Sixteen),
+            (data.to_vec(), BitDepth::Sixteen)
+        );
+    }
+
+    #[test]
+    fn eight_bit_png() {
+        let data = [0, 257, 40000, 65535, 12345, 0, 54321, 1000];
+        let (read, depth) = round_trip(&data, "png", BitDepth::Eight);
+        assert_eq!(depth, BitDepth::Eight);
+        let rounded: Vec<u16> = to_rgba8(&data).iter().map(|&c| c as u16 * 257).collect();
+        assert_eq!(read, rounded);
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        let path = std::env::temp_dir().join("pixelsort-export-test.txt");
+        assert!(write_image(&[0; 8], 2, 1, &path, BitDepth::Eight, 90).is_err());
+        // Data of the wrong size
+        let path = std::env::temp_dir().join("pixelsort-export-test-size.png");
+        assert!(write_image(&[0; 4], 2, 1, &path, BitDepth::Sixteen, 90).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, render::render_resource::Extent3d};
+use bevy_egui::EguiContext;
+use pixelsort_core::Pipeline;
+
+use crate::{
+    mask::{restore_overlay, Mask},
+    working::{self, WorkingImage},
+    Canvas, PixelsortImage, ResortEvent,
+};
+
+// Copy of the full precision source image and the mask overlay, which has the same size, taken
+// before a destructive operation. Changes to only the mask don't copy the source.
+struct ImageSnapshot {
+    data: Option<Vec<u16>>,
+    mask: Option<Vec<u8>>,
+    size: Extent3d,
+}
+
+impl ImageSnapshot {
+    fn of(data: Option<&[u16]>, size: Extent3d, mask: Option<&Image>) -> Self {
+        Self {
+            data: data.map(<[u16]>::to_vec),
+            // A mask of another size doesn't apply to the image
+            mask: mask
+                .filter(|mask| mask.texture_descriptor.size == size)
+                .map(|mask| mask.data.clone()),
+            size,
+        }
+    }
+}
+
+// State before a change. Only changes to the source image or the mask store a copy of them.
+struct Snapshot {
+    pipeline: Pipeline,
+    source: Option<ImageSnapshot>,
+}
+
+impl Snapshot {
+    fn memory(&self) -> usize {
+        self.source.as_ref().map_or(0, |image| {
+            image.data.as_ref().map_or(0, |data| data.len() * 2)
+                + image.mask.as_ref().map_or(0, Vec::len)
+        })
+    }
+}
+
+// Undo and redo stacks, limited to a maximum amount of image memory.
+pub(crate) struct History {
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    // The pipeline as of the last recorded change.
+    committed: Pipeline,
+    pub(crate) memory_limit_mb: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            committed: Pipeline::default(),
+            memory_limit_mb: 512,
+        }
+    }
+}
+
+impl History {
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    // Record the source image and mask before they are changed by a destructive operation.
+    pub(crate) fn record_image(&mut self, source: &[u16], size: Extent3d, mask: Option<&Image>) {
+        self.record(Snapshot {
+            pipeline: self.committed.clone(),
+            source: Some(ImageSnapshot::of(Some(source), size, mask)),
+        });
+    }
+
+    // Record the mask of an image of the size before it is painted on.
+    pub(crate) fn record_mask(&mut self, size: Extent3d, mask: Option<&Image>) {
+        self.record(Snapshot {
+            pipeline: self.committed.clone(),
+            source: Some(ImageSnapshot::of(None, size, mask)),
+        });
+    }
+
+    // Forget all image and mask snapshots, as they don't fit a newly loaded image.
+    pub(crate) fn forget_images(&mut self) {
+        self.undo.retain(|snapshot| snapshot.source.is_none());
+        self.redo.retain(|snapshot| snapshot.source.is_none());
+    }
+
+    fn record(&mut self, snapshot: Snapshot) {
+        self.undo.push_back(snapshot);
+        self.redo.clear();
+        self.enforce_limit();
+    }
+
+    // Drop the oldest snapshots until the image memory fits the limit.
+    pub(crate) fn enforce_limit(&mut self) {
+        let limit = self.memory_limit_mb * 1024 * 1024;
+        let mut used: usize = self
+            .undo
+            .iter()
+            .chain(self.redo.iter())
+            .map(Snapshot::memory)
+            .sum();
+        while used > limit {
+            // Redo snapshots are further away from the current state than undo ones.
+            let dropped = if !self.redo.is_empty() {
+                self.redo.remove(0)
+            } else if let Some(snapshot) = self.undo.pop_front() {
+                snapshot
+            } else {
+                break;
+            };
+            used -= dropped.memory();
+        }
+    }
+}
+
+// Event dispatched to undo or redo the last change.
+pub(crate) enum HistoryEvent {
+    Undo,
+    Redo,
+}
+
+// System which records changes to the pipeline, once the mouse is released so drags become one entry.
+pub(crate) fn record_pipeline(
+    pipeline: Res<Pipeline>,
+    mut history: ResMut<History>,
+    mouse: Res<Input<MouseButton>>,
+) {
+    if *pipeline != history.committed && !mouse.pressed(MouseButton::Left) {
+        let previous = std::mem::replace(&mut history.committed, pipeline.clone());
+        history.record(Snapshot {
+            pipeline: previous,
+            source: None,
+        });
+    }
+}
+
+// System which turns Ctrl+Z and Ctrl+Shift+Z into history events.
+pub(crate) fn history_keys(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut evt: EventWriter<HistoryEvent>,
+) {
+    // Leave the shortcuts to text fields while they are focused
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if ctrl && keys.just_pressed(KeyCode::Z) {
+        evt.send(if shift {
+            HistoryEvent::Redo
+        } else {
+            HistoryEvent::Undo
+        });
+    }
+}
+
+// System which restores snapshots from the history
+pub(crate) fn undo_redo(
+    mut evt: EventReader<HistoryEvent>,
+    mut history: ResMut<History>,
+    mut pipeline: ResMut<Pipeline>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    mut working: ResMut<WorkingImage>,
+    canvas: Res<Canvas>,
+    mut mask: ResMut<Mask>,
+    mut resort: EventWriter<ResortEvent>,
+    mut commands: Commands,
+) {
+    for event in evt.iter() {
+        let snapshot = match event {
+            HistoryEvent::Undo => history.undo.pop_back(),
+            HistoryEvent::Redo => history.redo.pop(),
+        };
+        let Some(snapshot) = snapshot else {
+            continue;
+        };
+
+        // Store the current state so the change can be redone or undone again.
+        let source = pixelsimage
+            .as_ref()
+            .and_then(|pixelsimg| images.get(&pixelsimg.source));
+        let overlay = mask
+            .overlay
+            .as_ref()
+            .and_then(|overlay| images.get(overlay));
+        // Only copy the source if the snapshot replaces it
+        let current = Snapshot {
+            pipeline: history.committed.clone(),
+            source: snapshot.source.as_ref().zip(source).map(|(image, source)| {
+                let data = image.data.as_ref().map(|_| &working.source[..]);
+                ImageSnapshot::of(data, source.texture_descriptor.size, overlay)
+            }),
+        };
+        match event {
+            HistoryEvent::Undo => history.redo.push(current),
+            HistoryEvent::Redo => history.undo.push_back(current),
+        }
+
+        history.committed = snapshot.pipeline.clone();
+        *pipeline = snapshot.pipeline;
+
+        if let (Some(image), Some(pixelsimg)) = (snapshot.source, pixelsimage.as_ref()) {
+            // Mask changes leave the source and its sorted result as they are, so only the
+            // pixels whose mask changed are sorted again
+            if let Some(data) = image.data {
+                working.set_source(data);
+                let source = images.get_mut(&pixelsimg.source).expect("unreachable");
+                source.texture_descriptor.size = image.size;
+                working::set_texture(source, &working.source);
+                let dest = images.get_mut(&pixelsimg.dest).expect("unreachable");
+                dest.texture_descriptor.size = image.size;
+                working::set_texture(dest, &working.dest);
+            }
+            restore_overlay(
+                &mut mask,
+                image.mask,
+                image.size,
+                &mut images,
+                &canvas,
+                &mut commands,
+            );
+            // Force sprite reset, the size might have changed
+            commands
+                .entity(canvas.0.expect("unreachable"))
+                .insert(Sprite::default());
+            resort.send_default();
+        }
+    }
+    history.enforce_limit();
+}
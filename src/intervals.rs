@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::Extent3d,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use pixelsort_core::{stage_intervals_controlled, IntervalPixel, Pipeline, SortControl};
+
+use crate::{
+    canvas::{overlay_image, spawn_overlay},
+    mask,
+    working::WorkingImage,
+    Canvas, PixelsortImage, ResortEvent,
+};
+
+// Colours of the intervals, alternating along a row.
+const INTERVAL_COLORS: [[u8; 3]; 2] = [[255, 200, 0], [0, 160, 255]];
+// Colour of the parts of the intervals added by extending or merging them.
+const ADDED_COLOR: [u8; 3] = [255, 0, 255];
+// Alpha of the intervals drawn over the image.
+const OVERLAY_ALPHA: u8 = 160;
+
+// How the intervals of a stage are shown on the canvas.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub(crate) enum IntervalView {
+    Off,
+    // Over the sorted image
+    Overlay,
+    // Instead of the sorted image
+    Only,
+}
+
+// Intervals being found in the background, and the size of the image they are of.
+struct IntervalTask {
+    task: Task<Option<Vec<IntervalPixel>>>,
+    control: Arc<SortControl>,
+    size: Extent3d,
+}
+
+// Which intervals are shown, and the overlay they are drawn into.
+pub(crate) struct IntervalSettings {
+    pub(crate) view: IntervalView,
+    // Index of the stage whose intervals are shown
+    pub(crate) stage: usize,
+    image: Option<Handle<Image>>,
+    entity: Option<Entity>,
+    running: Option<IntervalTask>,
+}
+
+impl IntervalSettings {
+    // Stop finding the intervals, they are stale.
+    fn cancel(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.control.cancel();
+        }
+    }
+}
+
+impl Default for IntervalSettings {
+    fn default() -> Self {
+        Self {
+            view: IntervalView::Off,
+            stage: 0,
+            image: None,
+            entity: None,
+            running: None,
+        }
+    }
+}
+
+// Colour of a pixel of the overlay.
+fn interval_color(part: IntervalPixel, view: IntervalView) -> [u8; 4] {
+    let alpha = match view {
+        IntervalView::Only => 255,
+        _ => OVERLAY_ALPHA,
+    };
+    match part {
+        IntervalPixel::Matched(number) => {
+            let [r, g, b] = INTERVAL_COLORS[number % 2];
+            [r, g, b, alpha]
+        }
+        IntervalPixel::Added(_) => {
+            let [r, g, b] = ADDED_COLOR;
+            [r, g, b, alpha]
+        }
+        IntervalPixel::Unsorted => match view {
+            IntervalView::Only => [0, 0, 0, 255],
+            _ => [0; 4],
+        },
+    }
+}
+
+// System which finds the intervals of the selected stage in the background whenever they might
+// have changed, and draws them once they are found
+pub(crate) fn update_intervals(
+    mut settings: ResMut<IntervalSettings>,
+    pipeline: Res<Pipeline>,
+    mask: Res<mask::Mask>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    working: Res<WorkingImage>,
+    canvas: Res<Canvas>,
+    mut resort: EventReader<ResortEvent>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut visibilities: Query<&mut Visibility>,
+    mut last: Local<Option<(Pipeline, IntervalView, usize)>>,
+    mut commands: Commands,
+) {
+    let Some(pixelsimg) = pixelsimage else {
+        return;
+    };
+    finish_intervals(&mut settings, &mut images, &canvas, &mut commands);
+
+    // Changes to the image, the mask, the pipeline or what is shown
+    let source_changed = image_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == pixelsimg.source
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let resort_requested = resort.iter().count() > 0;
+    let state = (pipeline.clone(), settings.view, settings.stage);
+    if last.as_ref() == Some(&state)
+        && !source_changed
+        && !resort_requested
+        && !pixelsimg.is_changed()
+    {
+        return;
+    }
+    *last = Some(state);
+
+    if let Some(mut visibility) = settings
+        .entity
+        .and_then(|entity| visibilities.get_mut(entity).ok())
+    {
+        visibility.is_visible = settings.view != IntervalView::Off;
+    }
+    // The intervals of the previous settings are stale
+    settings.cancel();
+    if settings.view == IntervalView::Off || settings.stage >= pipeline.stages.len() {
+        return;
+    }
+    let Some(source) = images.get(&pixelsimg.source) else {
+        return;
+    };
+    let size = source.texture_descriptor.size;
+    let mask = mask::current_mask(&mask, &images, size);
+    let control = Arc::new(SortControl::default());
+    let task_control = control.clone();
+    let data = working.source.clone();
+    let pipeline = pipeline.clone();
+    let stage = settings.stage;
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        stage_intervals_controlled(
+            &data,
+            size.width as usize,
+            size.height as usize,
+            &pipeline,
+            stage,
+            mask.as_deref(),
+            &task_control,
+        )
+    });
+    settings.running = Some(IntervalTask {
+        task,
+        control,
+        size,
+    });
+}
+
+// Draw the intervals into the overlay once they are found
+fn finish_intervals(
+    settings: &mut IntervalSettings,
+    images: &mut Assets<Image>,
+    canvas: &Canvas,
+    commands: &mut Commands,
+) {
+    let Some(running) = settings.running.as_mut() else {
+        return;
+    };
+    let Some(result) = future::block_on(future::poll_once(&mut running.task)) else {
+        return;
+    };
+    let size = running.size;
+    settings.running = None;
+    let Some(intervals) = result else {
+        return;
+    };
+    let data: Vec<u8> = intervals
+        .into_iter()
+        .flat_map(|part| interval_color(part, settings.view))
+        .collect();
+
+    match settings
+        .image
+        .as_ref()
+        .and_then(|image| images.get_mut(image))
+    {
+        Some(image) => {
+            if image.texture_descriptor.size != size {
+                if let Some(entity) = settings.entity {
+                    // Force sprite reset, the size changed
+                    commands.entity(entity).insert(Sprite::default());
+                }
+            }
+            image.texture_descriptor.size = size;
+            image.data = data;
+        }
+        None => {
+            let image = images.add(overlay_image(size, data));
+            // Below the mask overlay
+            settings.entity = spawn_overlay(canvas, image.clone(), Vec3::Z * 0.5, commands);
+            settings.image = Some(image);
+        }
+    }
+}
@@ -1,22 +1,39 @@
-#![feature(array_chunks)]
-#![feature(let_chains)]
+// Bevy systems take all resources they use as arguments.
+#![allow(clippy::too_many_arguments)]
+
+use std::sync::Arc;
 
 use bevy::{
     ecs::system::{Command, Insert},
     prelude::*,
     render::{render_resource::Extent3d, texture::ImageSampler},
+    tasks::{AsyncComputeTaskPool, Task},
 };
 use bevy_asset_loader::prelude::*;
 use bevy_egui::EguiPlugin;
 use bevy_pancam::{PanCam, PanCamPlugin};
 use bevy_web_asset::WebAssetPlugin;
+use clap::Parser;
+use futures_lite::future;
+use history::History;
 use iyes_loopless::prelude::*;
 use iyes_progress::prelude::*;
-use rayon::prelude::*;
-
-mod sorting;
+use mask::{Mask, MaskEvent};
+use pixelsort_core::{Pipeline, SortCache, SortControl};
+use working::{BitDepth, WorkingImage};
+
+mod canvas;
+mod cli;
+mod compare;
+mod export;
+mod history;
+mod intervals;
+mod mask;
+mod open;
+mod presets;
+mod preview;
 mod ui;
-use sorting::{PixelOrdering, RowOp, Threshold};
+mod working;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum ImageStates {
@@ -29,13 +46,38 @@ fn main() {
     // Enable ansi on windows, if possible
     let _ = enable_ansi_support::enable_ansi_support();
 
+    // Sort without opening a window if a subcommand was given.
+    let cli = cli::Cli::parse();
+    if let Some(command) = cli.command {
+        std::process::exit(command.run());
+    }
+
     App::new()
         // Setup resources (global state) for this app.
         .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
         .insert_resource(Canvas(None))
-        .init_resource::<Settings>()
+        .init_resource::<Pipeline>()
         .add_event::<PersistEvent>()
         .add_event::<RotateEvent>()
+        .init_resource::<export::ExportSettings>()
+        .add_event::<export::ExportEvent>()
+        .init_resource::<presets::PresetSettings>()
+        .init_resource::<history::History>()
+        .add_event::<history::HistoryEvent>()
+        .add_event::<ResortEvent>()
+        .init_resource::<canvas::CenterPicker>()
+        .init_resource::<preview::ValueDragged>()
+        .init_resource::<Mask>()
+        .init_resource::<mask::Brush>()
+        .init_resource::<intervals::IntervalSettings>()
+        .init_resource::<compare::Compare>()
+        .add_event::<MaskEvent>()
+        .add_event::<open::OpenImageEvent>()
+        .add_event::<open::PasteEvent>()
+        .insert_resource(open::StartupImage(cli.image))
+        .init_resource::<open::OpenStatus>()
+        .init_resource::<WorkingImage>()
+        .init_resource::<Sorting>()
         // Setup states
         .add_loopless_state(ImageStates::Before)
         // Ass all plugins - WebAssetPlugin currently unused
@@ -43,7 +85,7 @@ fn main() {
         .add_plugins(DefaultPlugins)
         // Never seen the loading take long, this is likely only useful once WebAssetPlugin is used.
         .add_plugin(ProgressPlugin::new(ImageStates::Loading))
-        .add_plugin(PanCamPlugin::default())
+        .add_plugin(PanCamPlugin)
         .add_plugin(EguiPlugin)
         // State in which asset loading happenes.
         .add_loading_state(
@@ -54,14 +96,30 @@ fn main() {
         )
         // Systems which run in the main loop
         .add_startup_system(setup)
+        .add_startup_system(open::open_startup_image)
         .add_system(ui::ui)
-        .add_system(file_drop)
+        .add_system(open::file_drop)
+        .add_system(open::paste_image)
+        .add_system(open::open_image)
+        .add_system(open::check_loading.run_in_state(ImageStates::Loading))
         .add_system(persist)
+        .add_system(export::export)
+        .add_system(history::record_pipeline)
+        .add_system(history::history_keys)
+        .add_system(history::undo_redo)
+        .add_system(canvas::toggle_pancam)
+        .add_system(canvas::pick_center)
+        .add_system(mask::update_mask)
+        .add_system(mask::paint_mask)
+        .add_system(intervals::update_intervals)
+        .add_system(compare::update_compare)
+        .add_system(compare::hold_original)
         // These only run once a image was loaded.
         .add_system_set(
             ConditionSet::new()
                 .run_in_state(ImageStates::Loaded)
                 .with_system(update_img)
+                .with_system(finish_sort)
                 .with_system(rotate_img_90)
                 .into(),
         )
@@ -81,21 +139,9 @@ struct PixelsortImage {
     dest: Handle<Image>,
 }
 
-// All of the settings which can be set in the UI
-#[derive(Default, PartialEq, Clone)]
-struct Settings {
-    threshold: Threshold,
-    threshold_reverse: bool,
-    ordering: PixelOrdering,
-    ordering_reverse: bool,
-    extend_threshold_left: usize,
-    extend_threshold_right: usize,
-    merge_limit: usize,
-}
-
 impl FromWorld for PixelsortImage {
     fn from_world(world: &mut World) -> Self {
-        let (source_clone, source_handle_clone, canvas_entity) = {
+        let (source_image, canvas_entity, working, error) = {
             let cell = world.cell();
 
             // Load resources we want to use/edit from the world.
@@ -103,14 +149,38 @@ impl FromWorld for PixelsortImage {
                 .get_resource::<ImageAssets>()
                 .expect("Failed to get loaded image Asset");
 
-            let mut images = cell
-                .get_resource_mut::<Assets<Image>>()
+            let images = cell
+                .get_resource::<Assets<Image>>()
                 .expect("Faild to get image asset handler.");
 
-            // Get and clone source image (and set nearest sampler)
-            let mut source_image = images.get_mut(&image_assets.image).unwrap();
+            // The loaded image stays as it was decoded, the asset server hands it out again when
+            // the same file is opened. The source is a copy of it (with nearest sampler).
+            let loaded = images.get(&image_assets.image).unwrap();
+            let mut source_image = loaded.clone();
             source_image.sampler_descriptor = ImageSampler::nearest();
 
+            // Keep it at full precision for sorting, and show it as 8-bit
+            let size = source_image.texture_descriptor.size;
+            let (data, depth, error) = match working::texture_to_rgba16(loaded) {
+                Ok((data, depth)) => (data, depth, None),
+                // Show a transparent image of the same size instead of garbage
+                Err(e) => {
+                    let data = vec![0; (size.width * size.height) as usize * 4];
+                    (data, BitDepth::Eight, Some(e))
+                }
+            };
+            working::set_texture(&mut source_image, &data);
+            // A new revision, so sorts of the previous image are never taken for this one
+            let revision = cell
+                .get_resource::<WorkingImage>()
+                .map_or(0, |previous| previous.revision + 1);
+            let working = WorkingImage {
+                source: data.clone(),
+                dest: data,
+                depth,
+                revision,
+            };
+
             // get canvas entity
             let canvas = cell
                 .get_resource_mut::<Canvas>()
@@ -118,19 +188,26 @@ impl FromWorld for PixelsortImage {
 
             let canvas_entity = canvas.0.expect("Canvas not yet initialised.");
 
-            (
-                source_image.clone(),
-                image_assets.image.clone(),
-                canvas_entity,
-            )
+            (source_image, canvas_entity, working, error)
         };
-
-        // add image to Asset<Image> and get handle
-        let clone_handle = {
-            world
-                .get_resource_mut::<Assets<Image>>()
-                .unwrap()
-                .add(source_clone)
+        world.insert_resource(working);
+        let mut status = world.resource_mut::<open::OpenStatus>();
+        if error.is_some() {
+            status.error = error;
+        }
+        // The image has been read, its temporary copies aren't needed anymore
+        status.remove_temp_files();
+        // Snapshots of the previous image can't be restored onto the new one, neither can the
+        // mask be applied to it. Both stay if the new image fails to load.
+        world.resource_mut::<History>().forget_images();
+        world
+            .resource_mut::<Events<MaskEvent>>()
+            .send(MaskEvent::Clear);
+
+        // add the source image and its sorted copy to Asset<Image> and get their handles
+        let (source_handle, clone_handle) = {
+            let mut images = world.get_resource_mut::<Assets<Image>>().unwrap();
+            (images.add(source_image.clone()), images.add(source_image))
         };
 
         // Overwrite canvas entity handle.
@@ -148,7 +225,7 @@ impl FromWorld for PixelsortImage {
 
         // Return self with the new handles.
         Self {
-            source: source_handle_clone,
+            source: source_handle,
             dest: clone_handle,
         }
     }
@@ -192,7 +269,12 @@ fn persist(
     mut evt: EventReader<PersistEvent>,
     pixelsimage: Option<Res<PixelsortImage>>,
     mut images: ResMut<Assets<Image>>,
+    mut working: ResMut<WorkingImage>,
+    mut history: ResMut<History>,
+    mask: Res<Mask>,
     canvas: Res<Canvas>,
+    mut sorting: ResMut<Sorting>,
+    mut resort: EventWriter<ResortEvent>,
     mut commands: Commands,
 ) {
     if let Some(pixelsimg) = pixelsimage {
@@ -202,8 +284,23 @@ fn persist(
                 dest.data.clone()
             };
 
+            let overlay = mask
+                .overlay
+                .as_ref()
+                .and_then(|overlay| images.get(overlay));
+            let size = images
+                .get(&pixelsimg.source)
+                .unwrap()
+                .texture_descriptor
+                .size;
+            history.record_image(&working.source, size, overlay);
+            let sorted = working.dest.clone();
+            working.set_source(sorted);
             let source = images.get_mut(&pixelsimg.source).unwrap();
             source.data = dest_data;
+            // Sort the new source, a running sort is of the old one
+            sorting.cancel();
+            resort.send_default();
             // Force sprite reset
             commands
                 .entity(canvas.0.expect("unreachable"))
@@ -216,35 +313,53 @@ fn persist(
 #[derive(Default)]
 struct RotateEvent;
 
-// System which rotates the image
+// Rotate RGBA image data by 90 degrees clockwise.
+fn rotate_data<T: Copy>(data: &[T], w: usize, h: usize) -> Vec<T> {
+    let mut rotated = Vec::with_capacity(data.len());
+    for x in 0..w {
+        for y in (0..h).rev() {
+            // Rotate the image, assuming 4 channels are one pixel (rgba)
+            let index = (x + y * w) * 4;
+            rotated.extend_from_slice(&data[index..index + 4]);
+        }
+    }
+    rotated
+}
+
+// System which rotates the image, and the mask with it
 fn rotate_img_90(
     mut evt: EventReader<RotateEvent>,
     pixelsimage: Option<Res<PixelsortImage>>,
     mut images: ResMut<Assets<Image>>,
+    mut working: ResMut<WorkingImage>,
+    mut history: ResMut<History>,
+    mask: Res<Mask>,
     // needed to recreate the Sprite, forces it to re-size itself to the rotated size.
     canvas: Res<Canvas>,
+    mut sorting: ResMut<Sorting>,
+    mut resort: EventWriter<ResortEvent>,
     mut commands: Commands,
 ) {
     if let Some(pixelsimg) = pixelsimage {
         for _ in evt.iter() {
+            let overlay = mask
+                .overlay
+                .as_ref()
+                .and_then(|overlay| images.get(overlay));
+            let size = images
+                .get(&pixelsimg.source)
+                .unwrap()
+                .texture_descriptor
+                .size;
+            history.record_image(&working.source, size, overlay);
             let new_extent = {
                 let source = images.get_mut(&pixelsimg.source).expect("unreachable");
                 let (w, h) = source.size().into();
                 let w = w.round() as usize;
                 let h = h.round() as usize;
-                let src_data = source.data.clone();
-                let mut i_dest = 0;
-                for x in 0..w {
-                    for y in (0..h).rev() {
-                        // Rotate the image, assuming 4 bytes are one pixel (rgba)
-                        let index = (x + y * w) * 4;
-                        source.data[i_dest] = src_data[index];
-                        source.data[i_dest + 1] = src_data[index + 1];
-                        source.data[i_dest + 2] = src_data[index + 2];
-                        source.data[i_dest + 3] = src_data[index + 3];
-                        i_dest += 4;
-                    }
-                }
+                source.data = rotate_data(&source.data, w, h);
+                let rotated = rotate_data(&working.source, w, h);
+                working.set_source(rotated);
                 let extent = Extent3d {
                     width: h as u32,
                     height: w as u32,
@@ -259,6 +374,16 @@ fn rotate_img_90(
             // Resize the other, destination, image the same way.
             dest.reinterpret_size(new_extent);
             dest.data = src_clone;
+            // and the mask, which was the same size as the source before
+            if let Some(overlay) = mask.overlay.as_ref().and_then(|o| images.get_mut(o)) {
+                let (w, h) = (new_extent.height as usize, new_extent.width as usize);
+                overlay.data = rotate_data(&overlay.data, w, h);
+                overlay.reinterpret_size(new_extent);
+                mask::reset_overlay_sprite(&mask, &mut commands);
+            }
+            // Sort the rotated image, a running sort is of the old one
+            sorting.cancel();
+            resort.send_default();
             // Force sprite reset
             commands
                 .entity(canvas.0.expect("unreachable"))
@@ -267,74 +392,200 @@ fn rotate_img_90(
     }
 }
 
+// Event dispatched when the image needs to be sorted again, even though the pipeline didn't change.
+#[derive(Default)]
+struct ResortEvent;
+
+// Sorted image of a finished sort, along with the cache of full resolution sorts. None if the
+// sort was cancelled.
+type SortResult = Option<(Vec<u16>, Option<SortCache<u16>>)>;
+
+// Sort running in the background, the size and revision of the image it sorts and whether it is
+// a low resolution preview.
+struct SortTask {
+    task: Task<SortResult>,
+    control: Arc<SortControl>,
+    size: Extent3d,
+    revision: usize,
+    preview: bool,
+}
+
+// The sort running in the background, if any, and what the last full resolution sort left
+// behind for the next one.
+#[derive(Default)]
+pub(crate) struct Sorting {
+    running: Option<SortTask>,
+    // Kept by the running sort until it is done, and lost if it is cancelled
+    cache: Option<SortCache<u16>>,
+    // Revision of the source and the mask the cache was sorted with
+    revision: usize,
+    mask: Option<Vec<bool>>,
+    // A mask change waiting for the running sort, to sort only the changed pixels after it
+    pending: bool,
+}
+
+impl Sorting {
+    // How much of the image the running sort has sorted, from 0 to 1.
+    pub(crate) fn progress(&self) -> Option<f32> {
+        self.running
+            .as_ref()
+            .map(|running| running.control.progress())
+    }
+
+    // Stop the running sort, its result is stale.
+    pub(crate) fn cancel(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.control.cancel();
+        }
+    }
+}
+
+// System which starts sorting the image in the background when the pipeline or image changed,
+// cancelling the sort of the previous settings. While a value is dragged large images are sorted
+// at a lower resolution, and at full resolution once it is let go. When only the mask changed,
+// only the rows and paths through the changed pixels are sorted again.
 fn update_img(
-    pixelsimage: Option<ResMut<PixelsortImage>>,
-    mut images: ResMut<Assets<Image>>,
-    settings: Res<Settings>,
-    mut last_settings: Local<Settings>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    images: Res<Assets<Image>>,
+    working: Res<WorkingImage>,
+    pipeline: Res<Pipeline>,
+    mask: Res<Mask>,
+    mut sorting: ResMut<Sorting>,
+    mut last_pipeline: Local<Option<Pipeline>>,
+    mut resort: EventReader<ResortEvent>,
+    value_dragged: Res<preview::ValueDragged>,
+    mut previewed: Local<bool>,
 ) {
-    // Check if settings have changed
-    if *settings == *last_settings {
+    // Check if the pipeline or image has changed, or a resort was requested
+    let resort_requested = resort.iter().count() > 0;
+    let image_changed = pixelsimage
+        .as_ref()
+        .is_some_and(|pixelsimg| pixelsimg.is_changed());
+    let dragging = value_dragged.0;
+    // The preview is replaced by the full resolution result once the drag ends
+    let settled = *previewed && !dragging;
+    let pipeline_changed = last_pipeline.as_ref() != Some(&*pipeline);
+    let pending = sorting.pending && sorting.running.is_none();
+    if !pipeline_changed && !resort_requested && !image_changed && !settled && !pending {
+        return;
+    }
+    // The source is the same, so a running full resolution sort still holds for everything but
+    // the mask. Wait for it rather than losing its cache.
+    let source_unchanged = !image_changed && working.revision == sorting.revision;
+    if sorting
+        .running
+        .as_ref()
+        .is_some_and(|running| !running.preview)
+        && !pipeline_changed
+        && !settled
+        && source_unchanged
+    {
+        sorting.pending = true;
         return;
     }
-    *last_settings = settings.clone();
+    sorting.pending = false;
+    *last_pipeline = Some(pipeline.clone());
 
     if let Some(pixelsimg) = pixelsimage {
         if let Some(source) = images.get(&pixelsimg.source) {
-            let (w, _) = source.size().into();
+            let (w, h) = source.size().into();
             let w = w.round() as usize;
-            let src_data = source.data.clone();
-            if let Some(dest) = images.get_mut(&pixelsimg.dest) {
-                let width = w * 4;
-
-                // Overwrite the source completely, otherwise there will be artifacts from previous sorts.
-                dest.data = src_data;
-
-                // Paralell loop over the rows of pixels
-                dest.data.par_chunks_exact_mut(width).for_each(|row| {
-                    let mut row_op = RowOp::default();
-                    // Apply the threshold settings to this row
-                    row_op.apply_threshold(row, w, &settings);
-
-                    // loop over all parts of the row matched by the threshold
-                    for range in row_op.slices.iter() {
-                        // and sort them
-                        let sorted = &settings.ordering.order(
-                            row[range.0 * 4..range.1 * 4].array_chunks::<4>().copied(),
-                            settings.ordering_reverse,
-                        );
-                        // and copy them back into the row
-                        row[range.0 * 4..range.1 * 4].copy_from_slice(&sorted[..]);
-                    }
-                });
-            }
+            let h = h.round() as usize;
+            let size = source.texture_descriptor.size;
+            let mask = mask::current_mask(&mask, &images, size);
+            // The result of the previous settings is stale
+            sorting.cancel();
+            let control = Arc::new(SortControl::default());
+            let task_control = control.clone();
+            // Sort from a copy of the source, which overwrites dest once done, otherwise there
+            // will be artifacts from previous sorts.
+            let data = working.source.clone();
+            let pipeline = pipeline.clone();
+            let preview_scale = preview::preview_scale(w, h).filter(|_| dragging);
+            let task = match preview_scale {
+                Some(scale) => AsyncComputeTaskPool::get().spawn(async move {
+                    preview::sort_preview(
+                        &data,
+                        w,
+                        h,
+                        &pipeline,
+                        mask.as_deref(),
+                        scale,
+                        &task_control,
+                    )
+                    .map(|data| (data, None))
+                }),
+                None => {
+                    // The cache only holds while dest is the result of the last sort of the
+                    // same source, then only the pixels whose mask changed are sorted again.
+                    let cache = sorting.cache.take().filter(|_| source_unchanged);
+                    let changed = cache.as_ref().map(|_| {
+                        mask::changed_pixels(sorting.mask.as_deref(), mask.as_deref(), w * h)
+                    });
+                    let mut cache = cache.unwrap_or_default();
+                    let mut dest = working.dest.clone();
+                    let revision = working.revision;
+                    sorting.revision = revision;
+                    sorting.mask = mask.clone();
+                    AsyncComputeTaskPool::get().spawn(async move {
+                        cache
+                            .sort(
+                                &data,
+                                revision,
+                                &mut dest,
+                                w,
+                                h,
+                                &pipeline,
+                                mask.as_deref(),
+                                changed.as_deref(),
+                                &task_control,
+                            )
+                            .then_some((dest, Some(cache)))
+                    })
+                }
+            };
+            *previewed = preview_scale.is_some();
+            sorting.running = Some(SortTask {
+                task,
+                control,
+                size,
+                revision: working.revision,
+                preview: *previewed,
+            });
         }
     }
 }
 
-fn file_drop(mut dnd_evr: EventReader<FileDragAndDrop>, mut commands: Commands) {
-    // Loop over all drop events
-    for ev in dnd_evr.iter() {
-        if let FileDragAndDrop::DroppedFile { id: _, path_buf } = ev {
-            if let Some(extension) = path_buf.extension() {
-                // Find the correct file extensions
-                match extension.to_str() {
-                    Some("png") | Some("jpg") | Some("jpeg") => {
-                        // And dynamically load the image at runtime
-                        commands.add(RegisterStandardDynamicAsset {
-                            key: "image",
-                            asset: StandardDynamicAsset::File {
-                                path: path_buf.as_os_str().to_str().expect("").to_owned(),
-                            },
-                        });
-                        // Transition the state to Loading, to trigger asset loading.
-                        commands.insert_resource(NextState(ImageStates::Loading));
-                    }
-                    // Any other file type is unsupported
-                    Some(_) => println!("Unsupported file type dropped."),
-                    None => println!("Cant deal with non utf-8 paths."),
-                }
-            }
+// System which swaps the sorted image in once the background sort is done
+fn finish_sort(
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    mut working: ResMut<WorkingImage>,
+    mut sorting: ResMut<Sorting>,
+) {
+    let Some(running) = sorting.running.as_mut() else {
+        return;
+    };
+    let Some(result) = future::block_on(future::poll_once(&mut running.task)) else {
+        return;
+    };
+    let (size, revision, preview) = (running.size, running.revision, running.preview);
+    sorting.running = None;
+    let (Some((data, cache)), Some(pixelsimg)) = (result, pixelsimage) else {
+        return;
+    };
+    // Unless the image was replaced, rotated or persisted in the meantime
+    if let Some(dest) = images
+        .get_mut(&pixelsimg.dest)
+        .filter(|dest| dest.texture_descriptor.size == size && working.revision == revision)
+    {
+        // Previews are only shown, exports use the last full resolution result
+        if preview {
+            working::set_texture(dest, &data);
+        } else {
+            working.dest = data;
+            working::set_texture(dest, &working.dest);
+            sorting.cache = cache;
         }
     }
 }
@@ -0,0 +1,397 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, render::render_resource::Extent3d};
+use bevy_egui::EguiContext;
+use image::imageops::FilterType;
+
+use crate::{
+    canvas::{cursor_on_canvas, overlay_image, spawn_overlay},
+    history::History,
+    Canvas, PixelsortImage, ResortEvent,
+};
+
+// Colour of the overlay over the pixels the mask keeps from being sorted.
+const BLOCKED_COLOR: [u8; 3] = [255, 0, 64];
+// Alpha of the overlay over fully blocked pixels, pixels with less than half of it are sorted.
+const BLOCKED_ALPHA: u8 = 128;
+
+// Mask restricting where the image is sorted. It is stored as the overlay shown over the canvas,
+// which is transparent where sorting is allowed. Soft brushes leave partly transparent pixels.
+#[derive(Default)]
+pub(crate) struct Mask {
+    pub(crate) overlay: Option<Handle<Image>>,
+    pub(crate) entity: Option<Entity>,
+    pub(crate) show: bool,
+    pub(crate) status: Option<String>,
+}
+
+// Event dispatched to load a mask from a file, invert it or remove it.
+pub(crate) enum MaskEvent {
+    Load(PathBuf),
+    Invert,
+    Clear,
+}
+
+// Brush used to paint the mask on the canvas.
+pub(crate) struct Brush {
+    pub(crate) radius: f32,
+    // How much of the radius is painted fully, the rest fades out
+    pub(crate) hardness: f32,
+    pub(crate) erase: bool,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            radius: 20.,
+            hardness: 0.5,
+            erase: false,
+        }
+    }
+}
+
+// Read a mask image and scale it to the image size. White, opaque pixels allow sorting, black
+// or transparent ones don't.
+pub(crate) fn load_mask(path: &Path, width: u32, height: u32) -> Result<Vec<bool>, String> {
+    let mut mask = image::open(path)
+        .map_err(|e| format!("Failed to open mask {}: {}", path.display(), e))?
+        .into_rgba8();
+    if mask.dimensions() != (width, height) {
+        mask = image::imageops::resize(&mask, width, height, FilterType::Nearest);
+    }
+    Ok(mask
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            let luminance = (r as usize * 2 + g as usize * 3 + b as usize) / 6;
+            luminance.min(a as usize) >= 128
+        })
+        .collect())
+}
+
+// Which pixels a mask overlay allows to be sorted.
+fn overlay_to_mask(overlay: &[u8]) -> Vec<bool> {
+    overlay
+        .chunks_exact(4)
+        .map(|pixel| pixel[3] < BLOCKED_ALPHA / 2)
+        .collect()
+}
+
+// Which pixels of an image of the size the mask allows to be sorted, if there is a mask which
+// still fits the image.
+pub(crate) fn current_mask(
+    mask: &Mask,
+    images: &Assets<Image>,
+    size: Extent3d,
+) -> Option<Vec<bool>> {
+    mask.overlay
+        .as_ref()
+        .and_then(|overlay| images.get(overlay))
+        .filter(|overlay| overlay.texture_descriptor.size == size)
+        .map(|overlay| overlay_to_mask(&overlay.data))
+}
+
+// Which of the pixels are allowed to be sorted by one mask but not the other, no mask allowing
+// every pixel.
+pub(crate) fn changed_pixels(
+    before: Option<&[bool]>,
+    after: Option<&[bool]>,
+    pixels: usize,
+) -> Vec<bool> {
+    let allowed = |mask: Option<&[bool]>, i: usize| mask.is_none_or(|mask| mask[i]);
+    (0..pixels)
+        .map(|i| allowed(before, i) != allowed(after, i))
+        .collect()
+}
+
+fn overlay_pixel(alpha: u8) -> [u8; 4] {
+    [BLOCKED_COLOR[0], BLOCKED_COLOR[1], BLOCKED_COLOR[2], alpha]
+}
+
+fn mask_to_overlay(mask: &[bool]) -> Vec<u8> {
+    mask.iter()
+        .flat_map(|&allowed| overlay_pixel(if allowed { 0 } else { BLOCKED_ALPHA }))
+        .collect()
+}
+
+// Add a new overlay of the size to the images and show it over the canvas.
+fn create_overlay(
+    mask: &mut Mask,
+    data: Vec<u8>,
+    size: Extent3d,
+    images: &mut Assets<Image>,
+    canvas: &Canvas,
+    commands: &mut Commands,
+) {
+    let overlay = images.add(overlay_image(size, data));
+    set_overlay(mask, Some(overlay), canvas, commands);
+    mask.show = true;
+}
+
+// System which loads, inverts and removes the mask, recording the mask before so it can be
+// undone, and shows or hides its overlay
+pub(crate) fn update_mask(
+    mut evt: EventReader<MaskEvent>,
+    mut mask: ResMut<Mask>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    canvas: Res<Canvas>,
+    mut visibilities: Query<&mut Visibility>,
+    mut resort: EventWriter<ResortEvent>,
+    mut commands: Commands,
+) {
+    for event in evt.iter() {
+        match event {
+            MaskEvent::Load(path) => {
+                let Some(size) = pixelsimage
+                    .as_ref()
+                    .and_then(|pixelsimg| images.get(&pixelsimg.source))
+                    .map(|source| source.texture_descriptor.size)
+                else {
+                    mask.status = Some("Load an image before its mask.".to_owned());
+                    continue;
+                };
+                let loaded = match load_mask(path, size.width, size.height) {
+                    Ok(loaded) => loaded,
+                    Err(e) => {
+                        mask.status = Some(e);
+                        continue;
+                    }
+                };
+                let overlay = mask.overlay.as_ref().and_then(|o| images.get(o));
+                history.record_mask(size, overlay);
+                let data = mask_to_overlay(&loaded);
+                create_overlay(&mut mask, data, size, &mut images, &canvas, &mut commands);
+                mask.status = None;
+            }
+            MaskEvent::Invert => {
+                let Some(overlay) = mask.overlay.as_ref().and_then(|o| images.get_mut(o)) else {
+                    continue;
+                };
+                history.record_mask(overlay.texture_descriptor.size, Some(overlay));
+                for pixel in overlay.data.chunks_exact_mut(4) {
+                    pixel[3] = BLOCKED_ALPHA - pixel[3].min(BLOCKED_ALPHA);
+                }
+            }
+            MaskEvent::Clear => {
+                let Some(overlay) = mask.overlay.as_ref().and_then(|o| images.get(o)) else {
+                    continue;
+                };
+                history.record_mask(overlay.texture_descriptor.size, Some(overlay));
+                set_overlay(&mut mask, None, &canvas, &mut commands);
+            }
+        }
+        resort.send_default();
+    }
+
+    if let Some(mut visibility) = mask
+        .entity
+        .and_then(|entity| visibilities.get_mut(entity).ok())
+    {
+        visibility.is_visible = mask.show;
+    }
+}
+
+// Replace the overlay sprite drawn over the canvas.
+fn set_overlay(
+    mask: &mut Mask,
+    overlay: Option<Handle<Image>>,
+    canvas: &Canvas,
+    commands: &mut Commands,
+) {
+    if let Some(entity) = mask.entity.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if let Some(ref overlay) = overlay {
+        mask.entity = spawn_overlay(canvas, overlay.clone(), Vec3::Z, commands);
+    }
+    mask.overlay = overlay;
+}
+
+// Restore the overlay from a copy of its data and size, removing it if there was no mask.
+pub(crate) fn restore_overlay(
+    mask: &mut Mask,
+    data: Option<Vec<u8>>,
+    size: Extent3d,
+    images: &mut Assets<Image>,
+    canvas: &Canvas,
+    commands: &mut Commands,
+) {
+    let overlay = mask.overlay.as_ref().and_then(|o| images.get_mut(o));
+    match (data, overlay) {
+        (Some(data), Some(overlay)) => {
+            overlay.texture_descriptor.size = size;
+            overlay.data = data;
+            reset_overlay_sprite(mask, commands);
+        }
+        (Some(data), None) => create_overlay(mask, data, size, images, canvas, commands),
+        (None, Some(_)) => set_overlay(mask, None, canvas, commands),
+        (None, None) => (),
+    }
+}
+
+// Resize the overlay sprite after the size of the mask changed.
+pub(crate) fn reset_overlay_sprite(mask: &Mask, commands: &mut Commands) {
+    if let Some(entity) = mask.entity {
+        commands.entity(entity).insert(Sprite::default());
+    }
+}
+
+// Whether the brush is held, painting instead of panning.
+pub(crate) fn brush_held(keys: &Input<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::LShift, KeyCode::RShift])
+}
+
+// Paint a single dab of the brush centred on the position in pixels.
+fn paint_dab(overlay: &mut Image, brush: &Brush, center: Vec2) {
+    let size = overlay.texture_descriptor.size;
+    let (width, height) = (size.width as i32, size.height as i32);
+    let radius = brush.radius.max(0.5);
+    let hard_radius = radius * brush.hardness.clamp(0., 1.);
+    let min = (center - radius).floor().max(Vec2::ZERO);
+    let max = (center + radius).ceil();
+    for y in min.y as i32..(max.y as i32).min(height) {
+        for x in min.x as i32..(max.x as i32).min(width) {
+            let distance = Vec2::new(x as f32 + 0.5, y as f32 + 0.5).distance(center);
+            if distance > radius {
+                continue;
+            }
+            // Fully painted inside the hard radius, fading out towards the edge
+            let strength = if distance <= hard_radius {
+                1.
+            } else {
+                1. - (distance - hard_radius) / (radius - hard_radius)
+            };
+            let painted = (strength * BLOCKED_ALPHA as f32).round() as u8;
+            let alpha = &mut overlay.data[(x + y * width) as usize * 4 + 3];
+            // Strokes don't build up, each pixel keeps its strongest dab
+            *alpha = if brush.erase {
+                (*alpha).max(painted)
+            } else {
+                (*alpha).min(BLOCKED_ALPHA - painted)
+            };
+        }
+    }
+}
+
+// System which paints the mask while the brush is held and the canvas dragged
+pub(crate) fn paint_mask(
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    brush: Res<Brush>,
+    mut mask: ResMut<Mask>,
+    (windows, cameras, transforms): (
+        Res<Windows>,
+        Query<(&Camera, &GlobalTransform)>,
+        Query<&GlobalTransform>,
+    ),
+    mut egui_context: ResMut<EguiContext>,
+    canvas: Res<Canvas>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: ResMut<History>,
+    mut resort: EventWriter<ResortEvent>,
+    mut commands: Commands,
+    mut last_position: Local<Option<Vec2>>,
+) {
+    if !brush_held(&keys) || !mouse.pressed(MouseButton::Left) {
+        *last_position = None;
+        return;
+    }
+    let starting = mouse.just_pressed(MouseButton::Left);
+    // Strokes start on the canvas, not on the settings
+    if (!starting && last_position.is_none())
+        || (starting && egui_context.ctx_mut().wants_pointer_input())
+    {
+        return;
+    }
+    let Some(pixelsimg) = pixelsimage else {
+        return;
+    };
+    let Some(source) = images.get(&pixelsimg.source) else {
+        return;
+    };
+    let size = source.texture_descriptor.size;
+    let image_size = Vec2::new(size.width as f32, size.height as f32);
+    let canvas_transform = canvas.0.and_then(|entity| transforms.get(entity).ok());
+    let position = match (canvas_transform, cameras.get_single()) {
+        (Some(canvas_transform), Ok(camera)) => {
+            cursor_on_canvas(&windows, camera, canvas_transform, image_size)
+        }
+        _ => None,
+    };
+    let Some(position) = position.map(|position| position * image_size) else {
+        return;
+    };
+
+    if starting {
+        let overlay = mask
+            .overlay
+            .as_ref()
+            .and_then(|overlay| images.get(overlay));
+        history.record_mask(size, overlay);
+        // A new mask starts out the opposite of what the brush paints
+        if overlay.is_none_or(|overlay| overlay.texture_descriptor.size != size) {
+            let alpha = if brush.erase { 0 } else { BLOCKED_ALPHA };
+            let pixels = (size.width * size.height) as usize;
+            let data = overlay_pixel(alpha).repeat(pixels);
+            create_overlay(&mut mask, data, size, &mut images, &canvas, &mut commands);
+        }
+    }
+    let Some(overlay) = mask.overlay.as_ref().and_then(|o| images.get_mut(o)) else {
+        return;
+    };
+    // Dabs close enough together to leave no gaps when the cursor moves fast
+    let from = last_position.unwrap_or(position);
+    let spacing = (brush.radius / 4.).max(0.5);
+    let steps = (from.distance(position) / spacing).ceil().max(1.) as usize;
+    for step in 1..=steps {
+        paint_dab(
+            overlay,
+            &brush,
+            from.lerp(position, step as f32 / steps as f32),
+        );
+    }
+    *last_position = Some(position);
+    resort.send_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_from_image() {
+        let path = std::env::temp_dir().join(format!("pixelsort-mask-{}.png", std::process::id()));
+        // White, black, transparent white, and greys just above and below half
+        let pixels: [[u8; 4]; 4] = [
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+            [255, 255, 255, 0],
+            [128, 128, 128, 255],
+        ];
+        let mut image = image::RgbaImage::new(4, 2);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            pixel.0 = match y {
+                0 => pixels[x as usize],
+                _ => [127, 127, 127, 255],
+            };
+        }
+        image.save(&path).unwrap();
+        let same_size = load_mask(&path, 4, 2);
+        let scaled = load_mask(&path, 8, 2);
+        let _ = std::fs::remove_file(&path);
+
+        let row = [true, false, false, true];
+        assert_eq!(
+            same_size.unwrap(),
+            row.iter().chain(&[false; 4]).copied().collect::<Vec<_>>()
+        );
+        // Scaled to the size of the image without blending pixels
+        let wide: Vec<bool> = row.iter().flat_map(|&allowed| [allowed; 2]).collect();
+        assert_eq!(scaled.unwrap(), [&wide[..], &[false; 8]].concat());
+
+        assert!(load_mask(&path, 4, 2).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{asset::LoadState, prelude::*};
+use bevy_asset_loader::prelude::*;
+use bevy_egui::EguiContext;
+use image::{io::Reader, ImageFormat};
+use iyes_loopless::prelude::*;
+
+use crate::{export::EXPORT_FORMATS, ImageStates, PixelsortImage};
+
+// Image formats which can be opened, the same as can be exported as they depend on the same
+// features.
+pub(crate) const OPEN_FORMATS: &[(&str, &[&str])] = EXPORT_FORMATS;
+
+// Event dispatched to load a new image from a file.
+pub(crate) struct OpenImageEvent(pub(crate) PathBuf);
+
+// Event dispatched to open the image on the clipboard.
+#[derive(Default)]
+pub(crate) struct PasteEvent;
+
+// Image given on the command line, opened once the app has started.
+pub(crate) struct StartupImage(pub(crate) Option<PathBuf>);
+
+// The image being loaded, and why the last one failed to open.
+#[derive(Default)]
+pub(crate) struct OpenStatus {
+    pub(crate) error: Option<String>,
+    loading: Option<String>,
+    // Number of files copied to the temporary directory so far
+    copies: usize,
+    // Temporary files the image is being loaded from, deleted once it is done
+    temp_files: Vec<PathBuf>,
+}
+
+impl OpenStatus {
+    // Delete the temporary files of the image being loaded, once it has been loaded or failed to
+    pub(crate) fn remove_temp_files(&mut self) {
+        for path in self.temp_files.drain(..) {
+            // Nothing to do if they are already gone
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Which format an image is in, judging by its contents if they are recognized, otherwise by its
+// extension. Only the header is read, to catch files which aren't images early.
+fn image_format(path: &Path) -> Result<ImageFormat, String> {
+    let reader = Reader::open(path)
+        .and_then(Reader::with_guessed_format)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let format = reader
+        .format()
+        .filter(|&format| {
+            OPEN_FORMATS.iter().any(|(_, extensions)| {
+                extensions
+                    .iter()
+                    .any(|ext| ImageFormat::from_extension(ext) == Some(format))
+            })
+        })
+        .ok_or_else(|| {
+            format!(
+                "Can't open {}, its format is not supported.",
+                path.display()
+            )
+        })?;
+    reader
+        .into_dimensions()
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    Ok(format)
+}
+
+// Unique path in the temporary directory for a copy of an image.
+fn temp_path(name: &str, number: usize, extension: &str) -> PathBuf {
+    // A new name every time, the asset server would reuse an image already loaded from a path.
+    std::env::temp_dir().join(format!(
+        "pixelsort-{}-{}-{}.{}",
+        name,
+        std::process::id(),
+        number,
+        extension
+    ))
+}
+
+// Path the image can be loaded from by the asset server, which picks the decoder by the
+// extension and only takes utf-8 paths. Images which don't fit are copied to one that does.
+fn loadable_path(path: &Path, status: &mut OpenStatus) -> Result<String, String> {
+    let format = image_format(path)?;
+    let matching_extension = ImageFormat::from_path(path).ok() == Some(format);
+    if let (true, Some(path)) = (matching_extension, path.to_str()) {
+        return Ok(path.to_owned());
+    }
+    status.copies += 1;
+    let copy = temp_path("open", status.copies, format.extensions_str()[0]);
+    std::fs::copy(path, &copy).map_err(|e| format!("Failed to copy {}: {}", path.display(), e))?;
+    status.temp_files.push(copy.clone());
+    copy.into_os_string()
+        .into_string()
+        .map_err(|_| "Cant deal with non utf-8 paths.".to_owned())
+}
+
+// System which loads the images of open events as the new source image
+pub(crate) fn open_image(
+    mut evt: EventReader<OpenImageEvent>,
+    mut status: ResMut<OpenStatus>,
+    mut commands: Commands,
+) {
+    for OpenImageEvent(path) in evt.iter() {
+        let path = match loadable_path(path, &mut status) {
+            Ok(path) => path,
+            Err(e) => {
+                status.error = Some(e);
+                status.remove_temp_files();
+                continue;
+            }
+        };
+        // Dynamically load the image at runtime
+        commands.add(RegisterStandardDynamicAsset {
+            key: "image",
+            asset: StandardDynamicAsset::File { path: path.clone() },
+        });
+        // Transition the state to Loading, to trigger asset loading.
+        commands.insert_resource(NextState(ImageStates::Loading));
+        status.error = None;
+        status.loading = Some(path);
+    }
+}
+
+// System which watches the image being loaded, and leaves the loading state if it failed to
+// decode, which would otherwise wait for it forever
+pub(crate) fn check_loading(
+    mut status: ResMut<OpenStatus>,
+    asset_server: Res<AssetServer>,
+    pixelsimage: Option<Res<PixelsortImage>>,
+    mut commands: Commands,
+) {
+    let Some(path) = status.loading.clone() else {
+        return;
+    };
+    match asset_server.get_load_state(path.as_str()) {
+        LoadState::Loaded => status.loading = None,
+        LoadState::Failed => {
+            status.error = Some(format!("Failed to load {}.", path));
+            status.loading = None;
+            status.remove_temp_files();
+            // Go back to the previous image, if there was one
+            commands.insert_resource(NextState(if pixelsimage.is_some() {
+                ImageStates::Loaded
+            } else {
+                ImageStates::Before
+            }));
+        }
+        _ => (),
+    }
+}
+
+// System which opens the image given on the command line
+pub(crate) fn open_startup_image(
+    startup_image: Res<StartupImage>,
+    mut open: EventWriter<OpenImageEvent>,
+) {
+    if let Some(ref path) = startup_image.0 {
+        open.send(OpenImageEvent(path.clone()));
+    }
+}
+
+// System which opens dropped files
+pub(crate) fn file_drop(
+    mut dnd_evr: EventReader<FileDragAndDrop>,
+    mut open: EventWriter<OpenImageEvent>,
+) {
+    // Loop over all drop events
+    for ev in dnd_evr.iter() {
+        if let FileDragAndDrop::DroppedFile { id: _, path_buf } = ev {
+            open.send(OpenImageEvent(path_buf.clone()));
+        }
+    }
+}
+
+// Write the image on the clipboard to a temporary PNG file, so it can be loaded like any other.
+fn save_clipboard_image(number: usize) -> Result<PathBuf, String> {
+    let image = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .map_err(|e| format!("No image on the clipboard: {}", e))?;
+    let buffer = image::RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or("The clipboard image has an invalid size.")?;
+    let path = temp_path("clipboard", number, "png");
+    buffer
+        .save(&path)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// System which turns Ctrl+V into paste events, and opens the image on the clipboard
+pub(crate) fn paste_image(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut evt: EventReader<PasteEvent>,
+    mut open: EventWriter<OpenImageEvent>,
+    mut status: ResMut<OpenStatus>,
+    mut pasted: Local<usize>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    // Leave pasting to text fields while they are focused
+    let shortcut =
+        ctrl && keys.just_pressed(KeyCode::V) && !egui_context.ctx_mut().wants_keyboard_input();
+    if evt.iter().count() == 0 && !shortcut {
+        return;
+    }
+    *pasted += 1;
+    match save_clipboard_image(*pasted) {
+        Ok(path) => {
+            status.temp_files.push(path.clone());
+            open.send(OpenImageEvent(path));
+        }
+        Err(e) => status.error = Some(e),
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+use pixelsort_core::Pipeline;
+use ron::ser::PrettyConfig;
+
+// Directory in which presets are stored, relative to the working directory.
+pub(crate) const PRESET_DIR: &str = "presets";
+pub(crate) const PRESET_EXTENSION: &str = "ron";
+
+// Preset state set in the UI, and the result of the last save or load.
+#[derive(Default)]
+pub(crate) struct PresetSettings {
+    pub(crate) name: String,
+    pub(crate) selected: Option<PathBuf>,
+    pub(crate) status: Option<Result<String, String>>,
+}
+
+// Read a pipeline from a preset file.
+pub(crate) fn load_preset(path: &Path) -> Result<Pipeline, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    ron::from_str(&text).map_err(|e| format!("Invalid preset {}: {}", path.display(), e))
+}
+
+// Write a pipeline to a preset file, creating its directory if needed.
+pub(crate) fn save_preset(path: &Path, pipeline: &Pipeline) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(pipeline, PrettyConfig::default())
+        .map_err(|e| format!("Failed to serialize preset: {}", e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// Path of the preset with the given name in the presets directory.
+pub(crate) fn preset_path(name: &str) -> PathBuf {
+    Path::new(PRESET_DIR).join(format!("{}.{}", name, PRESET_EXTENSION))
+}
+
+// All presets in the presets directory, sorted by name.
+pub(crate) fn list_presets() -> Vec<PathBuf> {
+    let mut presets: Vec<PathBuf> = std::fs::read_dir(PRESET_DIR)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == PRESET_EXTENSION))
+                .collect()
+        })
+        .unwrap_or_default();
+    presets.sort();
+    presets
+}
+
+// Name of a preset as shown in the UI.
+pub(crate) fn preset_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use pixelsort_core::{DistanceMetric, SortDirection, Threshold};
+
+    use super::*;
+
+    // The preset example in the readme parses as the stages it describes
+    #[test]
+    fn readme_example() {
+        let readme = include_str!("../Readme.md");
+        let example = readme
+            .split("```ron\n")
+            .nth(1)
+            .and_then(|rest| rest.split("```").next())
+            .expect("readme has a preset example");
+        let pipeline: Pipeline = ron::from_str(example).expect("preset example parses");
+        assert_eq!(pipeline.stages.len(), 2);
+        assert_eq!(pipeline.stages[0].threshold, Threshold::Luminance(120.));
+        assert_eq!(pipeline.stages[0].merge_limit, 2);
+        assert_eq!(
+            pipeline.stages[1].threshold,
+            Threshold::ColorSimilarity(200., [0, 255, 0], DistanceMetric::Redmean)
+        );
+        assert_eq!(pipeline.stages[1].direction, SortDirection::Vertical);
+    }
+}
@@ -0,0 +1,111 @@
+use pixelsort_core::{sort_image_controlled, Pipeline, SortControl};
+
+// Number of pixels images are scaled down to for previews while a value is dragged.
+const PREVIEW_PIXELS: f32 = 512. * 512.;
+
+// Whether a value of the pipeline is being dragged in the settings window.
+#[derive(Default)]
+pub(crate) struct ValueDragged(pub(crate) bool);
+
+// Factor to scale an image by for a preview, if it is large enough to need one.
+pub(crate) fn preview_scale(width: usize, height: usize) -> Option<f32> {
+    let pixels = (width * height) as f32;
+    (pixels > PREVIEW_PIXELS).then(|| (PREVIEW_PIXELS / pixels).sqrt())
+}
+
+// Resize data with the number of values per pixel to a new size, taking the nearest pixel.
+fn scale_data<T: Copy>(
+    data: &[T],
+    per_pixel: usize,
+    (width, height): (usize, usize),
+    (new_width, new_height): (usize, usize),
+) -> Vec<T> {
+    let mut scaled = Vec::with_capacity(new_width * new_height * per_pixel);
+    for y in 0..new_height {
+        let src_y = y * height / new_height;
+        for x in 0..new_width {
+            let src = (src_y * width + x * width / new_width) * per_pixel;
+            scaled.extend_from_slice(&data[src..src + per_pixel]);
+        }
+    }
+    scaled
+}
+
+// Sort an RGBA16 image scaled down by the factor, with the pipeline scaled along with it, and
+// scale the result back up to the size of the image. None if the sort was cancelled.
+pub(crate) fn sort_preview(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    pipeline: &Pipeline,
+    mask: Option<&[bool]>,
+    scale: f32,
+    control: &SortControl,
+) -> Option<Vec<u16>> {
+    let size = (width, height);
+    let small = (
+        ((width as f32 * scale).round() as usize).max(1),
+        ((height as f32 * scale).round() as usize).max(1),
+    );
+    let mut preview = scale_data(data, 4, size, small);
+    let mask = mask.map(|mask| scale_data(mask, 1, size, small));
+    // Lengths follow the width, which the rounding may have changed a little
+    let pipeline = pipeline.scaled(small.0 as f32 / width as f32);
+    sort_image_controlled(
+        &mut preview,
+        small.0,
+        small.1,
+        &pipeline,
+        mask.as_deref(),
+        control,
+    )
+    .then(|| scale_data(&preview, 4, small, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_to_nearest_pixel() {
+        // 4x2 pixels of two values each, numbered by position
+        let data: Vec<u8> = (0..8).flat_map(|i| [i, 100 + i]).collect();
+        assert_eq!(scale_data(&data, 2, (4, 2), (2, 1)), [0, 100, 2, 102]);
+        assert_eq!(scale_data(&data, 2, (4, 2), (4, 2)), data);
+        let grey = [1, 2, 3, 4];
+        assert_eq!(
+            scale_data(&grey, 1, (2, 2), (4, 3)),
+            [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4]
+        );
+    }
+
+    #[test]
+    fn only_large_images_are_previewed() {
+        assert_eq!(preview_scale(512, 512), None);
+        let scale = preview_scale(2048, 1024).unwrap();
+        assert!((2048. * scale * 1024. * scale - PREVIEW_PIXELS).abs() < 1.);
+    }
+
+    // Previews come back at the size of the image, sorted like the scaled down image
+    #[test]
+    fn preview_size() {
+        let (width, height) = (1024, 600);
+        let data: Vec<u16> = (0..width * height * 4).map(|i| (i * 7919) as u16).collect();
+        let scale = preview_scale(width, height).unwrap();
+        let pipeline = Pipeline::default();
+        let preview = sort_preview(
+            &data,
+            width,
+            height,
+            &pipeline,
+            None,
+            scale,
+            &SortControl::default(),
+        )
+        .unwrap();
+        assert_eq!(preview.len(), data.len());
+        let cancelled = SortControl::default();
+        cancelled.cancel();
+        assert!(sort_preview(&data, width, height, &pipeline, None, scale, &cancelled).is_none());
+    }
+}
@@ -1,75 +1,599 @@
+use std::mem::discriminant;
+
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContext};
+use strum::IntoEnumIterator;
+
+use pixelsort_core::{DistanceMetric, Pipeline, PixelOrdering, SortDirection, Stage, Threshold};
 
 use crate::{
-    sorting::{PixelOrdering, Threshold},
-    PersistEvent, RotateEvent, Settings,
+    canvas::{center_mut, CenterPicker},
+    compare::{Compare, CompareMode},
+    export::{ExportEvent, ExportSettings, EXPORT_FORMATS},
+    history::{History, HistoryEvent},
+    intervals::{IntervalSettings, IntervalView},
+    mask::{Brush, Mask, MaskEvent},
+    open::{OpenImageEvent, OpenStatus, PasteEvent, OPEN_FORMATS},
+    presets::{list_presets, load_preset, preset_name, preset_path, save_preset, PresetSettings},
+    preview::ValueDragged,
+    PersistEvent, RotateEvent, Sorting,
 };
 
+// Changes to the list of stages, applied after the stages are drawn.
+enum StageAction {
+    MoveUp(usize),
+    MoveDown(usize),
+    Duplicate(usize),
+    Remove(usize),
+}
+
 pub(crate) fn ui(
     mut egui_context: ResMut<EguiContext>,
-    mut settings: ResMut<Settings>,
+    mut pipeline: ResMut<Pipeline>,
     mut rotate: EventWriter<RotateEvent>,
     mut persist: EventWriter<PersistEvent>,
+    mut export: EventWriter<ExportEvent>,
+    mut export_settings: ResMut<ExportSettings>,
+    mut preset_settings: ResMut<PresetSettings>,
+    mut history: ResMut<History>,
+    mut history_events: EventWriter<HistoryEvent>,
+    (mut picker, mut value_dragged): (ResMut<CenterPicker>, ResMut<ValueDragged>),
+    (mut mask, mut mask_events, mut brush): (ResMut<Mask>, EventWriter<MaskEvent>, ResMut<Brush>),
+    sorting: Res<Sorting>,
+    mut interval_settings: ResMut<IntervalSettings>,
+    mut compare: ResMut<Compare>,
+    (mut open, mut paste, mut open_status): (
+        EventWriter<OpenImageEvent>,
+        EventWriter<PasteEvent>,
+        ResMut<OpenStatus>,
+    ),
 ) {
+    // Large images are sorted at a lower resolution while a value is dragged
+    let mut dragged = false;
     egui::Window::new("Settings")
         .resizable(true)
         .show(egui_context.ctx_mut(), |ui| {
+            let stage_count = pipeline.stages.len();
+            let mut action = None;
+            for (i, stage) in pipeline.stages.iter_mut().enumerate() {
+                ui.push_id(i, |ui| {
+                    if let Some(a) = stage_ui(stage, i, stage_count, &mut picker, &mut dragged, ui)
+                    {
+                        action = Some(a);
+                    }
+                });
+            }
+            // The stage being picked for may have moved to another index
+            if action.is_some() {
+                picker.0 = None;
+            }
+            match action {
+                Some(StageAction::MoveUp(i)) => pipeline.stages.swap(i - 1, i),
+                Some(StageAction::MoveDown(i)) => pipeline.stages.swap(i, i + 1),
+                Some(StageAction::Duplicate(i)) => {
+                    let stage = pipeline.stages[i].clone();
+                    pipeline.stages.insert(i + 1, stage);
+                }
+                Some(StageAction::Remove(i)) => {
+                    pipeline.stages.remove(i);
+                }
+                None => (),
+            }
+            if ui.add(egui::Button::new("Add Stage")).clicked() {
+                pipeline.stages.push(Stage::default());
+            }
+            intervals_ui(&mut interval_settings, pipeline.stages.len(), ui);
+            // The image is sorted in the background, show how far it got
+            if let Some(progress) = sorting.progress() {
+                ui.add(
+                    egui::ProgressBar::new(progress)
+                        .text(format!("Sorting… {:.0}%", progress * 100.)),
+                );
+            }
+            ui.separator();
+
             egui::Grid::new("my_grid")
                 .num_columns(2)
                 .spacing([40.0, 4.0])
                 .striped(true)
                 .show(ui, |ui| {
-                    threshold_ui(&mut settings, ui);
-                    ordering_ui(&mut settings, ui);
-                    ui.end_row();
+                    open_ui(&mut open, &mut paste, &mut open_status, ui);
                     if ui.add(egui::Button::new("Rotate 90")).clicked() {
                         rotate.send_default();
                     };
                     if ui.add(egui::Button::new("Persist")).clicked() {
                         persist.send_default();
                     }
+                    ui.end_row();
+                    compare_ui(&mut compare, ui);
+                    history_ui(&mut history, &mut history_events, ui);
+                    mask_ui(&mut mask, &mut mask_events, &mut brush, ui);
+                    export_ui(&mut export_settings, &mut export, ui);
+                    preset_ui(&mut pipeline, &mut preset_settings, ui);
                 })
         });
+    value_dragged.0 = dragged;
+}
+
+fn open_ui(
+    open: &mut EventWriter<OpenImageEvent>,
+    paste: &mut EventWriter<PasteEvent>,
+    status: &mut ResMut<OpenStatus>,
+    ui: &mut egui::Ui,
+) {
+    if ui.add(egui::Button::new("Open…")).clicked() {
+        // All formats at once, followed by each on its own
+        let all: Vec<&str> = OPEN_FORMATS
+            .iter()
+            .flat_map(|(_, extensions)| extensions.iter().copied())
+            .collect();
+        let dialog = OPEN_FORMATS.iter().fold(
+            rfd::FileDialog::new().add_filter("Images", &all),
+            |dialog, (name, extensions)| dialog.add_filter(*name, extensions),
+        );
+        if let Some(path) = dialog.pick_file() {
+            open.send(OpenImageEvent(path));
+        }
+    }
+    if ui
+        .add(egui::Button::new("Paste"))
+        .on_hover_text("Open the image on the clipboard, or press Ctrl+V")
+        .clicked()
+    {
+        paste.send_default();
+    }
+    ui.end_row();
+    // Show why the last image failed to open, until it is dismissed or another one is opened
+    if let Some(e) = status.error.clone() {
+        ui.label("");
+        ui.horizontal(|ui| {
+            ui.colored_label(egui::Color32::RED, e);
+            if ui.small_button("✖").on_hover_text("Dismiss").clicked() {
+                status.error = None;
+            }
+        });
+        ui.end_row();
+    }
+}
+
+// Draw the settings of a single stage, returning any change to the list of stages. `dragged` is
+// set if one of its values is being dragged.
+fn stage_ui(
+    stage: &mut Stage,
+    index: usize,
+    stage_count: usize,
+    picker: &mut CenterPicker,
+    dragged: &mut bool,
+    ui: &mut egui::Ui,
+) -> Option<StageAction> {
+    let mut action = None;
+    let title = if stage.muted {
+        format!("Stage {} (muted)", index + 1)
+    } else {
+        format!("Stage {}", index + 1)
+    };
+    egui::CollapsingHeader::new(title)
+        .id_source("stage")
+        .default_open(true)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut stage.muted, "Mute");
+                if ui.add_enabled(index > 0, egui::Button::new("⬆")).clicked() {
+                    action = Some(StageAction::MoveUp(index));
+                }
+                if ui
+                    .add_enabled(index + 1 < stage_count, egui::Button::new("⬇"))
+                    .clicked()
+                {
+                    action = Some(StageAction::MoveDown(index));
+                }
+                if ui.add(egui::Button::new("Duplicate")).clicked() {
+                    action = Some(StageAction::Duplicate(index));
+                }
+                if ui.add(egui::Button::new("Remove")).clicked() {
+                    action = Some(StageAction::Remove(index));
+                }
+            });
+            egui::Grid::new("stage_grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    threshold_ui(stage, dragged, ui);
+                    ordering_ui(stage, ui);
+                    ui.end_row();
+                    direction_ui(stage, index, picker, dragged, ui);
+                });
+        });
+    action
+}
+
+// Add the widget of a value of the pipeline, noting whether it is being dragged.
+fn value_ui(ui: &mut egui::Ui, dragged: &mut bool, widget: impl egui::Widget) -> egui::Response {
+    let response = ui.add(widget);
+    *dragged |= response.dragged();
+    response
+}
+
+fn intervals_ui(settings: &mut ResMut<IntervalSettings>, stage_count: usize, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Intervals:").on_hover_text(
+            "Show the intervals a stage sorts, alternating yellow and blue, with the parts added by Extend and Merge in magenta",
+        );
+        ui.selectable_value(&mut settings.view, IntervalView::Off, "Off");
+        ui.selectable_value(&mut settings.view, IntervalView::Overlay, "Overlay");
+        ui.selectable_value(&mut settings.view, IntervalView::Only, "Only");
+        // Stages are numbered from 1 in the settings
+        let mut stage = settings.stage.min(stage_count.saturating_sub(1)) + 1;
+        ui.label("Stage:");
+        ui.add(egui::DragValue::new(&mut stage).clamp_range(1..=stage_count.max(1)));
+        settings.stage = stage - 1;
+    });
+}
+
+fn compare_ui(compare: &mut ResMut<Compare>, ui: &mut egui::Ui) {
+    ui.label("Compare:")
+        .on_hover_text("Hold Space to see the original image");
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut compare.mode, CompareMode::Off, "Off");
+        ui.selectable_value(&mut compare.mode, CompareMode::Split, "Split");
+        ui.selectable_value(&mut compare.mode, CompareMode::SideBySide, "Side by Side");
+        if compare.mode == CompareMode::Split {
+            ui.add(egui::Slider::new(&mut compare.split, 0.0..=1.0).show_value(false));
+        }
+    });
+    ui.end_row();
+}
+
+fn history_ui(
+    history: &mut ResMut<History>,
+    history_events: &mut EventWriter<HistoryEvent>,
+    ui: &mut egui::Ui,
+) {
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(history.can_undo(), egui::Button::new("Undo"))
+            .on_hover_text("Ctrl+Z")
+            .clicked()
+        {
+            history_events.send(HistoryEvent::Undo);
+        }
+        if ui
+            .add_enabled(history.can_redo(), egui::Button::new("Redo"))
+            .on_hover_text("Ctrl+Shift+Z")
+            .clicked()
+        {
+            history_events.send(HistoryEvent::Redo);
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("History Limit (MB):");
+        if ui
+            .add(egui::DragValue::new(&mut history.memory_limit_mb).clamp_range(0..=16384))
+            .changed()
+        {
+            history.enforce_limit();
+        }
+    });
+    ui.end_row();
+}
+
+fn mask_ui(
+    mask: &mut ResMut<Mask>,
+    mask_events: &mut EventWriter<MaskEvent>,
+    brush: &mut ResMut<Brush>,
+    ui: &mut egui::Ui,
+) {
+    ui.label("Mask:");
+    ui.horizontal(|ui| {
+        if ui
+            .add(egui::Button::new("Load Mask…"))
+            .on_hover_text("Only the white parts of the mask are sorted")
+            .clicked()
+        {
+            // Masks are read like exported images are written
+            let dialog = EXPORT_FORMATS
+                .iter()
+                .fold(rfd::FileDialog::new(), |dialog, (name, extensions)| {
+                    dialog.add_filter(*name, extensions)
+                });
+            if let Some(path) = dialog.pick_file() {
+                mask_events.send(MaskEvent::Load(path));
+            }
+        }
+        let loaded = mask.overlay.is_some();
+        if ui
+            .add_enabled(loaded, egui::Button::new("Clear Mask"))
+            .clicked()
+        {
+            mask_events.send(MaskEvent::Clear);
+        }
+        if ui
+            .add_enabled(loaded, egui::Button::new("Invert Mask"))
+            .clicked()
+        {
+            mask_events.send(MaskEvent::Invert);
+        }
+        ui.add_enabled(loaded, egui::Checkbox::new(&mut mask.show, "Show"));
+    });
+    ui.end_row();
+    ui.label("Brush:")
+        .on_hover_text("Hold Shift and drag on the image to paint the mask");
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut brush.erase, false, "Add");
+        ui.selectable_value(&mut brush.erase, true, "Erase");
+        ui.label("Size:");
+        ui.add(
+            egui::DragValue::new(&mut brush.radius)
+                .clamp_range(1.0..=500.0)
+                .speed(0.2)
+                .suffix("px"),
+        );
+        ui.label("Hardness:");
+        ui.add(
+            egui::DragValue::new(&mut brush.hardness)
+                .clamp_range(0.0..=1.0)
+                .speed(0.005),
+        );
+    });
+    ui.end_row();
+    // Show why the last mask failed to load
+    if let Some(ref e) = mask.status {
+        ui.label("");
+        ui.colored_label(egui::Color32::RED, e);
+        ui.end_row();
+    }
+}
+
+fn export_ui(
+    export_settings: &mut ResMut<ExportSettings>,
+    export: &mut EventWriter<ExportEvent>,
+    ui: &mut egui::Ui,
+) {
+    if ui.add(egui::Button::new("Export…")).clicked() {
+        // Offer all enabled formats, the first one being the default.
+        let dialog = EXPORT_FORMATS
+            .iter()
+            .fold(rfd::FileDialog::new(), |dialog, (name, extensions)| {
+                dialog.add_filter(*name, extensions)
+            })
+            .set_file_name("sorted.png");
+        if let Some(path) = dialog.save_file() {
+            export.send(ExportEvent(path));
+        }
+    }
+    ui.horizontal(|ui| {
+        ui.label("JPEG Quality:");
+        ui.add(egui::DragValue::new(&mut export_settings.jpeg_quality).clamp_range(1..=100));
+    });
+    ui.end_row();
+    // Show the result of the last export
+    match export_settings.status {
+        Some(Ok(ref path)) => {
+            ui.label("");
+            ui.label(format!("Exported to {}", path.display()));
+            ui.end_row();
+        }
+        Some(Err(ref e)) => {
+            ui.label("");
+            ui.colored_label(egui::Color32::RED, e);
+            ui.end_row();
+        }
+        None => (),
+    }
+}
+
+fn preset_ui(
+    pipeline: &mut ResMut<Pipeline>,
+    preset_settings: &mut ResMut<PresetSettings>,
+    ui: &mut egui::Ui,
+) {
+    ui.label("Preset:");
+    ui.horizontal(|ui| {
+        let selected_text = preset_settings
+            .selected
+            .as_deref()
+            .map(preset_name)
+            .unwrap_or_default();
+        egui::ComboBox::from_id_source("preset")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                // Only look at the presets directory while the dropdown is open
+                for path in list_presets() {
+                    let name = preset_name(&path);
+                    ui.selectable_value(&mut preset_settings.selected, Some(path), name);
+                }
+            });
+        let selected = preset_settings.selected.clone();
+        if ui
+            .add_enabled(selected.is_some(), egui::Button::new("Load Preset"))
+            .clicked()
+        {
+            if let Some(path) = selected {
+                preset_settings.status = Some(load_preset(&path).map(|loaded| {
+                    **pipeline = loaded;
+                    format!("Loaded {}", preset_name(&path))
+                }));
+            }
+        }
+    });
+    ui.end_row();
+    ui.label("");
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut preset_settings.name).hint_text("Preset name"));
+        let name = preset_settings.name.trim().to_owned();
+        if ui
+            .add_enabled(!name.is_empty(), egui::Button::new("Save Preset"))
+            .clicked()
+        {
+            let path = preset_path(&name);
+            preset_settings.status = Some(save_preset(&path, pipeline).map(|_| {
+                preset_settings.selected = Some(path);
+                format!("Saved {}", name)
+            }));
+        }
+    });
+    ui.end_row();
+    // Show the result of the last save or load
+    match preset_settings.status {
+        Some(Ok(ref message)) => {
+            ui.label("");
+            ui.label(message);
+            ui.end_row();
+        }
+        Some(Err(ref e)) => {
+            ui.label("");
+            ui.colored_label(egui::Color32::RED, e);
+            ui.end_row();
+        }
+        None => (),
+    }
 }
 
-const DEFAULT_THRESHOLDS: [Threshold; 2] = [
+const DEFAULT_THRESHOLDS: [Threshold; 8] = [
     Threshold::Luminance(0.),
-    Threshold::ColorSimilarity(1000, [0, 255, 0]),
+    Threshold::ColorSimilarity(200., [0, 255, 0], DistanceMetric::Redmean),
+    Threshold::Hue(300., 60.),
+    Threshold::Saturation(0.5, 1.),
+    Threshold::Value(0.5, 1.),
+    Threshold::Edges(0.9, 1.),
+    Threshold::Random(50, 0),
+    Threshold::Fixed(50),
 ];
 
-fn threshold_ui(settings: &mut ResMut<Settings>, ui: &mut egui::Ui) {
+fn threshold_ui(stage: &mut Stage, dragged: &mut bool, ui: &mut egui::Ui) {
     ui.label("Threshold:");
     ui.horizontal(|ui| {
         egui::ComboBox::from_id_source("thresh")
-            .selected_text(format!("{}", settings.threshold))
+            .selected_text(format!("{}", stage.threshold))
             .show_ui(ui, |ui| {
                 for default in DEFAULT_THRESHOLDS {
                     let name = format!("{}", default);
-                    ui.selectable_value(&mut settings.threshold, default, name);
+                    ui.selectable_value(&mut stage.threshold, default, name);
                 }
             });
-        ui.toggle_value(&mut settings.threshold_reverse, "Invert");
+        // Random and fixed intervals match every pixel, the core ignores inverting them
+        let invertible = stage.threshold.is_invertible();
+        let invert = egui::SelectableLabel::new(stage.threshold_reverse && invertible, "Invert");
+        if ui
+            .add_enabled(invertible, invert)
+            .on_disabled_hover_text("Random and fixed intervals sort every pixel")
+            .clicked()
+        {
+            stage.threshold_reverse = !stage.threshold_reverse;
+        }
     });
     ui.end_row();
     ui.label("Threshold Values:");
     ui.horizontal(|ui| {
-        match settings.threshold {
+        match stage.threshold {
             Threshold::Luminance(ref mut val) => {
-                ui.add(
+                value_ui(
+                    ui,
+                    dragged,
                     egui::DragValue::new(val)
                         .clamp_range(0.0..=255.0)
                         .speed(0.1),
                 );
             }
-            Threshold::ColorSimilarity(ref mut val, ref mut color) => {
-                ui.add(egui::DragValue::new(val).clamp_range(0..=2500).speed(1.0));
+            Threshold::ColorSimilarity(ref mut val, ref mut color, ref mut metric) => {
+                let max = metric.max_distance();
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(val)
+                        .clamp_range(0.0..=max)
+                        .speed(max / 2500.),
+                );
                 ui.color_edit_button_srgb(color);
+                let previous = *metric;
+                metric_ui(metric, "thresh_metric", ui);
+                // Keep the threshold at the same place in the range of the new metric
+                if *metric != previous {
+                    *val *= metric.max_distance() / previous.max_distance();
+                }
+            }
+            Threshold::Hue(ref mut start, ref mut end) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(start)
+                        .clamp_range(0.0..=360.0)
+                        .speed(0.5)
+                        .suffix("°"),
+                );
+                ui.label("to");
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(end)
+                        .clamp_range(0.0..=360.0)
+                        .speed(0.5)
+                        .suffix("°"),
+                );
+            }
+            Threshold::Saturation(ref mut min, ref mut max)
+            | Threshold::Value(ref mut min, ref mut max) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(min)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.002),
+                );
+                ui.label("to");
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(max)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.002),
+                );
+            }
+            Threshold::Edges(ref mut sensitivity, ref mut blur) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(sensitivity)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.002),
+                );
+                ui.label("Blur:");
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(blur)
+                        .clamp_range(0.0..=20.0)
+                        .speed(0.05)
+                        .suffix("px"),
+                );
+            }
+            Threshold::Random(ref mut length, ref mut seed) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(length)
+                        .clamp_range(1..=2000)
+                        .suffix("px"),
+                );
+                ui.label("Seed:");
+                value_ui(ui, dragged, egui::DragValue::new(seed));
+            }
+            Threshold::Fixed(ref mut length) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(length)
+                        .clamp_range(1..=2000)
+                        .suffix("px"),
+                );
             }
         }
         ui.label("Merge:");
-        ui.add(
-            egui::DragValue::new(&mut settings.merge_limit)
+        value_ui(
+            ui,
+            dragged,
+            egui::DragValue::new(&mut stage.merge_limit)
                 .clamp_range(0..=500)
                 .speed(0.01),
         );
@@ -79,14 +603,18 @@ fn threshold_ui(settings: &mut ResMut<Settings>, ui: &mut egui::Ui) {
     ui.label("Extend:");
     ui.horizontal(|ui| {
         ui.label("Left:");
-        ui.add(
-            egui::DragValue::new(&mut settings.extend_threshold_left)
+        value_ui(
+            ui,
+            dragged,
+            egui::DragValue::new(&mut stage.extend_threshold_left)
                 .clamp_range(0..=500)
                 .speed(1.),
         );
         ui.label("Right:");
-        ui.add(
-            egui::DragValue::new(&mut settings.extend_threshold_right)
+        value_ui(
+            ui,
+            dragged,
+            egui::DragValue::new(&mut stage.extend_threshold_right)
                 .clamp_range(0..=500)
                 .speed(1.),
         );
@@ -94,31 +622,158 @@ fn threshold_ui(settings: &mut ResMut<Settings>, ui: &mut egui::Ui) {
     ui.end_row();
 }
 
-const DEFAULT_ORDERINGS: [PixelOrdering; 2] = [
+const DEFAULT_ORDERINGS: [PixelOrdering; 10] = [
     PixelOrdering::Luminance,
-    PixelOrdering::ColorSimilarity([0, 255, 0]),
+    PixelOrdering::ColorSimilarity([0, 255, 0], DistanceMetric::Redmean),
+    PixelOrdering::Hue,
+    PixelOrdering::Saturation,
+    PixelOrdering::Value,
+    PixelOrdering::Lightness,
+    PixelOrdering::Red,
+    PixelOrdering::Green,
+    PixelOrdering::Blue,
+    PixelOrdering::Alpha,
 ];
 
-fn ordering_ui(settings: &mut ResMut<Settings>, ui: &mut egui::Ui) {
+fn ordering_ui(stage: &mut Stage, ui: &mut egui::Ui) {
     ui.label("Ordering:");
     ui.horizontal(|ui| {
         egui::ComboBox::from_id_source("sortby")
-            .selected_text(format!("{}", settings.ordering))
+            .selected_text(format!("{}", stage.ordering))
             .show_ui(ui, |ui| {
                 for default in DEFAULT_ORDERINGS {
                     let name = format!("{}", default);
-                    ui.selectable_value(&mut settings.ordering, default, name);
+                    ui.selectable_value(&mut stage.ordering, default, name);
                 }
             });
-        ui.toggle_value(&mut settings.ordering_reverse, "Reverse");
+        ui.toggle_value(&mut stage.ordering_reverse, "Reverse");
     });
     ui.end_row();
     ui.label("");
-    match settings.ordering {
-        PixelOrdering::Luminance => (),
-        PixelOrdering::ColorSimilarity(ref mut color) => {
+    if let PixelOrdering::ColorSimilarity(ref mut color, ref mut metric) = stage.ordering {
+        ui.horizontal(|ui| {
             ui.color_edit_button_srgb(color);
-            ui.end_row();
+            metric_ui(metric, "sortby_metric", ui);
+        });
+        ui.end_row();
+    }
+}
+
+fn metric_ui(metric: &mut DistanceMetric, id: &str, ui: &mut egui::Ui) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(format!("{}", metric))
+        .show_ui(ui, |ui| {
+            for option in DistanceMetric::iter() {
+                let name = format!("{}", option);
+                ui.selectable_value(metric, option, name);
+            }
+        });
+}
+
+const DEFAULT_DIRECTIONS: [SortDirection; 8] = [
+    SortDirection::Horizontal,
+    SortDirection::Vertical,
+    SortDirection::Angle(45.),
+    SortDirection::Circles([0.5, 0.5]),
+    SortDirection::Spiral([0.5, 0.5], 16.),
+    SortDirection::Radial([0.5, 0.5]),
+    SortDirection::Edges,
+    SortDirection::Noise(0, 100., 1.),
+];
+
+fn direction_ui(
+    stage: &mut Stage,
+    index: usize,
+    picker: &mut CenterPicker,
+    dragged: &mut bool,
+    ui: &mut egui::Ui,
+) {
+    ui.label("Direction:");
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("direction")
+            .selected_text(format!("{}", stage.direction))
+            .show_ui(ui, |ui| {
+                for default in DEFAULT_DIRECTIONS {
+                    // Compare only the variant, so selecting it again keeps its values
+                    let selected = discriminant(&stage.direction) == discriminant(&default);
+                    let name = format!("{}", default);
+                    if ui.selectable_label(selected, name).clicked() && !selected {
+                        stage.direction = default;
+                    }
+                }
+            });
+        match stage.direction {
+            SortDirection::Angle(ref mut angle) => {
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(angle)
+                        .clamp_range(0.0..=360.0)
+                        .speed(0.5)
+                        .suffix("°"),
+                );
+            }
+            SortDirection::Spiral(_, ref mut spacing) => {
+                ui.label("Spacing:");
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(spacing)
+                        .clamp_range(1.0..=500.0)
+                        .speed(0.1),
+                );
+            }
+            SortDirection::Noise(ref mut seed, ref mut scale, ref mut strength) => {
+                value_ui(ui, dragged, egui::DragValue::new(seed).prefix("seed: "));
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(scale)
+                        .clamp_range(1.0..=1000.0)
+                        .prefix("scale: "),
+                );
+                value_ui(
+                    ui,
+                    dragged,
+                    egui::DragValue::new(strength)
+                        .clamp_range(0.0..=4.0)
+                        .speed(0.01)
+                        .prefix("strength: "),
+                );
+            }
+            _ => (),
         }
+    });
+    ui.end_row();
+    if let Some(center) = center_mut(&mut stage.direction) {
+        ui.label("Centre:");
+        ui.horizontal(|ui| {
+            value_ui(
+                ui,
+                dragged,
+                egui::DragValue::new(&mut center[0])
+                    .clamp_range(0.0..=1.0)
+                    .speed(0.002)
+                    .prefix("x: "),
+            );
+            value_ui(
+                ui,
+                dragged,
+                egui::DragValue::new(&mut center[1])
+                    .clamp_range(0.0..=1.0)
+                    .speed(0.002)
+                    .prefix("y: "),
+            );
+            // Toggle picking the centre by clicking on the image
+            let mut picking = picker.0 == Some(index);
+            if ui
+                .toggle_value(&mut picking, "Pick")
+                .on_hover_text("Click on the image to set the centre")
+                .changed()
+            {
+                picker.0 = picking.then_some(index);
+            }
+        });
+        ui.end_row();
     }
 }
@@ -0,0 +1,201 @@
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use image::ColorType;
+
+// Bit depth of the channels of the loaded image, which it is exported at.
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    // Bit depth of an image decoded with the image crate.
+    pub(crate) fn of(color: ColorType) -> Self {
+        if color.bytes_per_pixel() > color.channel_count() {
+            BitDepth::Sixteen
+        } else {
+            BitDepth::Eight
+        }
+    }
+}
+
+// Full precision RGBA16 copies of the source and sorted images, which are sorted and exported.
+// The textures shown on the canvas are 8-bit versions of them.
+#[derive(Default)]
+pub(crate) struct WorkingImage {
+    pub(crate) source: Vec<u16>,
+    pub(crate) dest: Vec<u16>,
+    pub(crate) depth: BitDepth,
+    // Counts the changes to the source, so sorts know when their earlier results no longer hold
+    pub(crate) revision: usize,
+}
+
+impl WorkingImage {
+    // Replace the source image, dest shows it unsorted until it is sorted again.
+    pub(crate) fn set_source(&mut self, source: Vec<u16>) {
+        self.dest = source.clone();
+        self.source = source;
+        self.revision += 1;
+    }
+}
+
+// Round 16-bit channels to 8 bits, 65535 becoming 255.
+pub(crate) fn to_rgba8(data: &[u16]) -> Vec<u8> {
+    data.iter()
+        .map(|&c| ((c as u32 + 128) / 257) as u8)
+        .collect()
+}
+
+// Convert the data of a texture in any of the formats Bevy loads images in to RGBA16, along with
+// the bit depth it had.
+pub(crate) fn texture_to_rgba16(image: &Image) -> Result<(Vec<u16>, BitDepth), String> {
+    let data = &image.data;
+    let bytes = || data.iter().map(|&c| c as u16 * 257);
+    let words = || {
+        data.chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+    };
+    let floats = || {
+        data.chunks_exact(4)
+            .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+            .map(|c| (c.clamp(0., 1.) * 65535.).round() as u16)
+    };
+    // The channels, and how many of them make up a pixel
+    let (channels, per_pixel, depth): (Vec<u16>, usize, BitDepth) =
+        match image.texture_descriptor.format {
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm => {
+                (bytes().collect(), 4, BitDepth::Eight)
+            }
+            TextureFormat::Bgra8UnormSrgb | TextureFormat::Bgra8Unorm => {
+                let mut channels: Vec<u16> = bytes().collect();
+                channels
+                    .chunks_exact_mut(4)
+                    .for_each(|pixel| pixel.swap(0, 2));
+                (channels, 4, BitDepth::Eight)
+            }
+            TextureFormat::R8Unorm => (bytes().collect(), 1, BitDepth::Eight),
+            TextureFormat::Rg8Unorm => (bytes().collect(), 2, BitDepth::Eight),
+            TextureFormat::R16Uint | TextureFormat::R16Unorm => {
+                (words().collect(), 1, BitDepth::Sixteen)
+            }
+            TextureFormat::Rg16Uint | TextureFormat::Rg16Unorm => {
+                (words().collect(), 2, BitDepth::Sixteen)
+            }
+            TextureFormat::Rgba16Uint | TextureFormat::Rgba16Unorm => {
+                (words().collect(), 4, BitDepth::Sixteen)
+            }
+            TextureFormat::R32Float => (floats().collect(), 1, BitDepth::Sixteen),
+            TextureFormat::Rg32Float => (floats().collect(), 2, BitDepth::Sixteen),
+            TextureFormat::Rgba32Float => (floats().collect(), 4, BitDepth::Sixteen),
+            format => {
+                return Err(format!(
+                    "Can't sort images loaded as {:?} textures.",
+                    format
+                ))
+            }
+        };
+    let size = image.texture_descriptor.size;
+    if channels.len() != (size.width * size.height) as usize * per_pixel {
+        return Err("Image data does not match its size.".to_owned());
+    }
+    // Grey channels become all three colour channels
+    let rgba = match per_pixel {
+        1 => channels.iter().flat_map(|&l| [l, l, l, u16::MAX]).collect(),
+        2 => channels
+            .chunks_exact(2)
+            .flat_map(|la| [la[0], la[0], la[0], la[1]])
+            .collect(),
+        _ => channels,
+    };
+    Ok((rgba, depth))
+}
+
+// Show RGBA16 data in a texture, as 8-bit sRGB.
+pub(crate) fn set_texture(image: &mut Image, data: &[u16]) {
+    image.texture_descriptor.format = TextureFormat::Rgba8UnormSrgb;
+    image.data = to_rgba8(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    use super::*;
+
+    fn texture(width: u32, height: u32, data: Vec<u8>, format: TextureFormat) -> Image {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        Image::new(size, TextureDimension::D2, data, format)
+    }
+
+    #[test]
+    fn rgba8_textures() {
+        let image = texture(
+            2,
+            1,
+            vec![0, 1, 128, 255, 255, 0, 7, 9],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let (data, depth) = texture_to_rgba16(&image).unwrap();
+        assert_eq!(data, [0, 257, 128 * 257, 65535, 65535, 0, 7 * 257, 9 * 257]);
+        assert_eq!(depth, BitDepth::Eight);
+        assert_eq!(to_rgba8(&data), image.data);
+
+        let image = texture(1, 1, vec![1, 2, 3, 4], TextureFormat::Bgra8UnormSrgb);
+        assert_eq!(
+            texture_to_rgba16(&image).unwrap().0,
+            [3 * 257, 2 * 257, 257, 4 * 257]
+        );
+    }
+
+    #[test]
+    fn rgba16_textures() {
+        let channels: [u16; 4] = [1, 300, 40000, 65535];
+        let bytes = channels.iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let image = texture(1, 1, bytes, TextureFormat::Rgba16Uint);
+        let (data, depth) = texture_to_rgba16(&image).unwrap();
+        assert_eq!(data, channels);
+        assert_eq!(depth, BitDepth::Sixteen);
+    }
+
+    #[test]
+    fn grey_textures() {
+        let image = texture(2, 1, vec![10, 200], TextureFormat::R8Unorm);
+        assert_eq!(
+            texture_to_rgba16(&image).unwrap().0,
+            [2570, 2570, 2570, 65535, 51400, 51400, 51400, 65535]
+        );
+        let image = texture(1, 1, vec![10, 20], TextureFormat::Rg8Unorm);
+        assert_eq!(
+            texture_to_rgba16(&image).unwrap().0,
+            [2570, 2570, 2570, 5140]
+        );
+        let bytes = [0.5f32, 2.].iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let image = texture(1, 1, bytes, TextureFormat::Rg32Float);
+        let (data, depth) = texture_to_rgba16(&image).unwrap();
+        assert_eq!(data, [32768, 32768, 32768, 65535]);
+        assert_eq!(depth, BitDepth::Sixteen);
+    }
+
+    #[test]
+    fn invalid_textures() {
+        let mut image = texture(2, 2, vec![0; 16], TextureFormat::Rgba8Unorm);
+        image.data.truncate(12);
+        assert!(texture_to_rgba16(&image).is_err());
+        let image = texture(1, 1, vec![0; 4], TextureFormat::R32Uint);
+        assert!(texture_to_rgba16(&image).is_err());
+    }
+
+    #[test]
+    fn bit_depths() {
+        assert_eq!(BitDepth::of(ColorType::Rgba8), BitDepth::Eight);
+        assert_eq!(BitDepth::of(ColorType::L8), BitDepth::Eight);
+        assert_eq!(BitDepth::of(ColorType::Rgb16), BitDepth::Sixteen);
+        assert_eq!(BitDepth::of(ColorType::La16), BitDepth::Sixteen);
+        assert_eq!(BitDepth::of(ColorType::Rgba32F), BitDepth::Sixteen);
+    }
+}